@@ -0,0 +1,277 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+// Manufacturer vignetting correction recorded in DNG opcode lists (the
+// FixVignetteRadial opcode written by Adobe's converter and some cameras).
+// libraw doesn't apply or expose these, so the IFDs are walked directly.
+
+const TAG_SUB_IFDS: u16 = 330;
+const TAG_OPCODE_LIST2: u16 = 51009;
+const TAG_OPCODE_LIST3: u16 = 51022;
+const OPCODE_FIX_VIGNETTE_RADIAL: u32 = 3;
+// Guards against malformed files
+const MAX_IFDS: usize = 32;
+const MAX_OPCODE_LIST_BYTES: u32 = 1 << 20;
+
+// Gain 1 + k0 r^2 + k1 r^4 + ... + k4 r^10 about `center` (relative to the
+// image size), r = 1 at the corner farthest from the centre
+#[derive(Clone, Copy)]
+pub struct VignetteProfile {
+    k: [f32; 5],
+    center: (f32, f32),
+}
+
+impl VignetteProfile {
+    // The same profile for the image rotated clockwise by `degrees`
+    pub fn rotated(self, degrees: u16) -> Self {
+        let (cx, cy) = self.center;
+        let center = match degrees {
+            90 => (1.0 - cy, cx),
+            180 => (1.0 - cx, 1.0 - cy),
+            270 => (cy, 1.0 - cx),
+            _ => (cx, cy),
+        };
+        VignetteProfile { center, ..self }
+    }
+
+    pub fn apply(&self, data: &mut [f32], w: usize, h: usize) {
+        let cx = self.center.0 * w as f32;
+        let cy = self.center.1 * h as f32;
+        let max_r2 = [
+            (0.0, 0.0),
+            (w as f32, 0.0),
+            (0.0, h as f32),
+            (w as f32, h as f32),
+        ]
+        .iter()
+        .map(|(x, y)| (x - cx) * (x - cx) + (y - cy) * (y - cy))
+        .fold(0.0f32, f32::max)
+        .max(1.0);
+
+        for (i, px) in data.chunks_exact_mut(4).enumerate() {
+            let dx = (i % w) as f32 + 0.5 - cx;
+            let dy = (i / w) as f32 + 0.5 - cy;
+            let r2 = (dx * dx + dy * dy) / max_r2;
+            // Horner in r^2
+            let gain = 1.0 + r2 * self.k.iter().rev().fold(0.0, |acc, k| acc * r2 + k);
+            px[0] *= gain;
+            px[1] *= gain;
+            px[2] *= gain;
+        }
+    }
+}
+
+struct TiffFile {
+    file: File,
+    little_endian: bool,
+}
+
+impl TiffFile {
+    fn bytes(&mut self, offset: u32, len: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset as u64)).ok()?;
+        self.file.read_exact(&mut buf).ok()?;
+        Some(buf)
+    }
+
+    fn u16_at(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        }
+    }
+
+    fn u32_at(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        }
+    }
+}
+
+// Opcode lists are always big-endian
+fn parse_opcode_list(data: &[u8]) -> Option<VignetteProfile> {
+    let be32 = |i: usize| {
+        data.get(i..i + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let count = be32(0)?;
+    let mut pos = 4;
+    for _ in 0..count {
+        let id = be32(pos)?;
+        let size = be32(pos + 12)? as usize;
+        let params = data.get(pos + 16..pos + 16 + size)?;
+        if id == OPCODE_FIX_VIGNETTE_RADIAL && size >= 7 * 8 {
+            let f = |i: usize| {
+                let mut b = [0; 8];
+                b.copy_from_slice(&params[i * 8..i * 8 + 8]);
+                f64::from_be_bytes(b) as f32
+            };
+            return Some(VignetteProfile {
+                k: [f(0), f(1), f(2), f(3), f(4)],
+                center: (f(5), f(6)),
+            });
+        }
+        pos += 16 + size;
+    }
+    None
+}
+
+// Looks through IFD0, its chain and SubIFDs for a vignetting opcode. None
+// for non-TIFF files and files without one.
+pub fn vignette_profile(path: &str) -> Option<VignetteProfile> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0; 8];
+    file.read_exact(&mut header).ok()?;
+    let little_endian = match &header[..4] {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let mut tiff = TiffFile {
+        file,
+        little_endian,
+    };
+
+    let mut pending = vec![tiff.u32_at(&header[4..])];
+    let mut visited = 0;
+    while let Some(offset) = pending.pop() {
+        visited += 1;
+        if offset == 0 || visited > MAX_IFDS {
+            continue;
+        }
+        let count = tiff.u16_at(&tiff.bytes(offset, 2)?) as usize;
+        let entries = tiff.bytes(offset + 2, count * 12 + 4)?;
+
+        for entry in entries[..count * 12].chunks_exact(12) {
+            let tag = tiff.u16_at(&entry[0..]);
+            let n = tiff.u32_at(&entry[4..]);
+            let value = tiff.u32_at(&entry[8..]);
+            match tag {
+                TAG_SUB_IFDS if n == 1 => pending.push(value),
+                TAG_SUB_IFDS if n as usize <= MAX_IFDS => {
+                    if let Some(offsets) = tiff.bytes(value, n as usize * 4) {
+                        pending.extend(offsets.chunks_exact(4).map(|b| tiff.u32_at(b)));
+                    }
+                }
+                TAG_OPCODE_LIST2 | TAG_OPCODE_LIST3 if n > 4 && n <= MAX_OPCODE_LIST_BYTES => {
+                    let profile = tiff
+                        .bytes(value, n as usize)
+                        .and_then(|list| parse_opcode_list(&list));
+                    if profile.is_some() {
+                        return profile;
+                    }
+                }
+                _ => {}
+            }
+        }
+        pending.push(tiff.u32_at(&entries[count * 12..]));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opcode(id: u32, params: &[u8]) -> Vec<u8> {
+        let mut op = Vec::new();
+        for v in [id, 0x0103_0000, 0, params.len() as u32] {
+            op.extend_from_slice(&v.to_be_bytes());
+        }
+        op.extend_from_slice(params);
+        op
+    }
+
+    fn vignette(k0: f64) -> Vec<u8> {
+        [k0, 0.0, 0.0, 0.0, 0.0, 0.5, 0.25]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+
+    fn opcode_list(ops: &[Vec<u8>]) -> Vec<u8> {
+        let mut list = (ops.len() as u32).to_be_bytes().to_vec();
+        ops.iter().for_each(|op| list.extend_from_slice(op));
+        list
+    }
+
+    #[test]
+    fn finds_vignette_after_other_opcodes() {
+        let list = opcode_list(&[opcode(1, &[0; 12]), opcode(3, &vignette(0.4))]);
+        let profile = parse_opcode_list(&list).unwrap();
+        assert_eq!(profile.k, [0.4, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(profile.center, (0.5, 0.25));
+    }
+
+    #[test]
+    fn truncated_opcode_lists_are_ignored() {
+        let list = opcode_list(&[opcode(3, &vignette(0.4))]);
+        for len in [0, 3, 4, 19, list.len() - 1] {
+            assert!(parse_opcode_list(&list[..len]).is_none());
+        }
+        // More opcodes claimed than present
+        let mut list = list;
+        list[..4].copy_from_slice(&5u32.to_be_bytes());
+        list.truncate(list.len() - 8);
+        assert!(parse_opcode_list(&list).is_none());
+        // Too few parameters for a radial profile
+        assert!(parse_opcode_list(&opcode_list(&[opcode(3, &[0; 48])])).is_none());
+    }
+
+    // Little-endian TIFF with one IFD at offset 8 holding `entries`, then
+    // `data` at offset 8 + the IFD size
+    fn tiff(entries: &[(u16, u32, u32)], next: u32, data: &[u8]) -> Vec<u8> {
+        let mut file = b"II*\0".to_vec();
+        file.extend_from_slice(&8u32.to_le_bytes());
+        file.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, n, value) in entries {
+            file.extend_from_slice(&tag.to_le_bytes());
+            file.extend_from_slice(&7u16.to_le_bytes());
+            file.extend_from_slice(&n.to_le_bytes());
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        file.extend_from_slice(&next.to_le_bytes());
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn profile_of(name: &str, bytes: &[u8]) -> Option<VignetteProfile> {
+        let path = std::env::temp_dir().join(format!("dng-{}-{}.dng", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let profile = vignette_profile(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        profile
+    }
+
+    #[test]
+    fn reads_opcode_list_from_ifd() {
+        let list = opcode_list(&[opcode(3, &vignette(0.3))]);
+        // Header (8) + count (2) + one entry (12) + next (4)
+        let file = tiff(&[(TAG_OPCODE_LIST3, list.len() as u32, 26)], 0, &list);
+        let profile = profile_of("ifd", &file).unwrap();
+        assert_eq!(profile.k[0], 0.3);
+
+        // The list points past the end of the file
+        let file = tiff(&[(TAG_OPCODE_LIST3, list.len() as u32, 4096)], 0, &list);
+        assert!(profile_of("past-end", &file).is_none());
+        assert!(profile_of("not-tiff", b"\xff\xd8\xff\xe0 jpeg").is_none());
+    }
+
+    #[test]
+    fn ifd_walk_is_bounded() {
+        // IFD chain pointing back at itself
+        assert!(profile_of("loop", &tiff(&[], 8, &[])).is_none());
+        // SubIFDs listing the same IFD over and over
+        let subs: Vec<u8> = (0..MAX_IFDS).flat_map(|_| 8u32.to_le_bytes()).collect();
+        let file = tiff(&[(TAG_SUB_IFDS, MAX_IFDS as u32, 26)], 8, &subs);
+        assert!(profile_of("subifds", &file).is_none());
+        // Oversized opcode list
+        let file = tiff(&[(TAG_OPCODE_LIST3, MAX_OPCODE_LIST_BYTES + 1, 26)], 0, &[]);
+        assert!(profile_of("oversized", &file).is_none());
+    }
+}
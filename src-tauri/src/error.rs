@@ -0,0 +1,46 @@
+use serde::Serialize;
+use std::fmt;
+
+// Error returned to the frontend as { kind, message } so the UI can tell
+// failures apart instead of matching on strings
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AppError {
+    Io(String),
+    UnsupportedFormat(String),
+    Decode(String),
+    Encode(String),
+    InvalidInput(String),
+    NoImageLoaded,
+    // Superseded by a newer request; not a failure to report
+    Cancelled,
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Io(msg) => write!(f, "I/O error: {}", msg),
+            AppError::UnsupportedFormat(msg) => write!(f, "Unsupported format: {}", msg),
+            AppError::Decode(msg) => write!(f, "Decode failed: {}", msg),
+            AppError::Encode(msg) => write!(f, "Export failed: {}", msg),
+            AppError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            AppError::NoImageLoaded => write!(f, "No image loaded"),
+            AppError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Commands that still return String errors can use `?` on AppError results
+impl From<AppError> for String {
+    fn from(e: AppError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io(e.to_string())
+    }
+}
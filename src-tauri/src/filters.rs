@@ -0,0 +1,429 @@
+// Spatial passes over RGBA f32 buffers. These need neighbouring pixels, so
+// they run over the whole image before the per-pixel `apply_processing`.
+
+// Rec.709 luminance plane from RGBA data
+pub fn luminance(data: &[f32]) -> Vec<f32> {
+    data.chunks_exact(4)
+        .map(|px| 0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2])
+        .collect()
+}
+
+// Box radii whose three successive passes approximate a Gaussian of `sigma`
+fn box_radii(sigma: f32) -> [usize; 3] {
+    let n = 3.0;
+    let w_ideal = (12.0 * sigma * sigma / n + 1.0).sqrt();
+    let mut wl = w_ideal.floor() as i32;
+    if wl % 2 == 0 {
+        wl -= 1;
+    }
+    let wu = wl + 2;
+    let m_ideal = (12.0 * sigma * sigma - n * (wl * wl) as f32 - 4.0 * n * wl as f32 - 3.0 * n)
+        / (-4.0 * wl as f32 - 4.0);
+    let m = m_ideal.round() as i32;
+
+    let mut radii = [0; 3];
+    for (i, r) in radii.iter_mut().enumerate() {
+        let size = if (i as i32) < m { wl } else { wu };
+        *r = ((size.max(1) - 1) / 2) as usize;
+    }
+    radii
+}
+
+// Running-sum box blur along one axis. `stride` steps between samples of a
+// line, `line_step` between lines. Edges are clamped.
+fn box_blur_axis(
+    src: &[f32],
+    dst: &mut [f32],
+    len: usize,
+    lines: usize,
+    stride: usize,
+    line_step: usize,
+    radius: usize,
+) {
+    if radius == 0 || len == 0 {
+        dst.copy_from_slice(src);
+        return;
+    }
+
+    let r = radius as isize;
+    let last = len as isize - 1;
+    let norm = 1.0 / (2 * radius + 1) as f32;
+
+    for line in 0..lines {
+        let base = line * line_step;
+        let at = |i: isize| src[base + i.clamp(0, last) as usize * stride];
+
+        let mut acc = 0.0;
+        for i in -r..=r {
+            acc += at(i);
+        }
+        for i in 0..len as isize {
+            dst[base + i as usize * stride] = acc * norm;
+            acc += at(i + r + 1) - at(i - r);
+        }
+    }
+}
+
+// Approximate Gaussian blur of a single w x h plane
+pub fn blur(plane: &[f32], w: usize, h: usize, sigma: f32) -> Vec<f32> {
+    let mut a = plane.to_vec();
+    if sigma <= 0.0 || w == 0 || h == 0 {
+        return a;
+    }
+
+    let mut b = vec![0.0; a.len()];
+    for radius in box_radii(sigma) {
+        box_blur_axis(&a, &mut b, w, h, 1, w, radius);
+        box_blur_axis(&b, &mut a, h, w, w, 1, radius);
+    }
+    a
+}
+
+// Bilinear sample of channel `c` at (x, y), clamped to the image
+fn sample_channel(data: &[f32], w: usize, h: usize, c: usize, x: f32, y: f32) -> f32 {
+    let x = x.clamp(0.0, (w - 1) as f32);
+    let y = y.clamp(0.0, (h - 1) as f32);
+    let x0 = x.floor() as usize;
+    let y0 = y.floor() as usize;
+    let x1 = (x0 + 1).min(w - 1);
+    let y1 = (y0 + 1).min(h - 1);
+    let fx = x - x0 as f32;
+    let fy = y - y0 as f32;
+
+    let at = |px: usize, py: usize| data[(py * w + px) * 4 + c];
+    let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+    let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+    top + (bottom - top) * fy
+}
+
+// Bilinear resample of a whole RGBA buffer to new_w x new_h, with pixel
+// centres aligned so the image doesn't shift
+pub fn resample(data: &[f32], w: usize, h: usize, new_w: usize, new_h: usize) -> Vec<f32> {
+    let sx = w as f32 / new_w as f32;
+    let sy = h as f32 / new_h as f32;
+    let mut out = vec![0.0; new_w * new_h * 4];
+    for (i, px) in out.chunks_exact_mut(4).enumerate() {
+        let x = ((i % new_w) as f32 + 0.5) * sx - 0.5;
+        let y = ((i / new_w) as f32 + 0.5) * sy - 0.5;
+        for (c, value) in px.iter_mut().enumerate() {
+            *value = sample_channel(data, w, h, c, x, y);
+        }
+    }
+    out
+}
+
+// Whether (x, y) samples real image data (half a pixel of slack). Geometric
+// passes mark samples from outside the source as transparent (alpha 0).
+fn in_bounds(w: usize, h: usize, x: f32, y: f32) -> bool {
+    x >= -0.5 && y >= -0.5 && x <= w as f32 - 0.5 && y <= h as f32 - 0.5
+}
+
+// Lateral CA correction: red and blue are magnified by their scale about the
+// image centre so they line up with green again
+pub fn apply_lateral_ca(data: &mut [f32], w: usize, h: usize, red_scale: f32, blue_scale: f32) {
+    if w == 0 || h == 0 {
+        return;
+    }
+    let src = data.to_vec();
+    let cx = (w as f32 - 1.0) / 2.0;
+    let cy = (h as f32 - 1.0) / 2.0;
+
+    for (c, scale) in [(0, red_scale), (2, blue_scale)] {
+        if scale == 1.0 || scale <= 0.0 {
+            continue;
+        }
+        for (i, px) in data.chunks_exact_mut(4).enumerate() {
+            let sx = cx + ((i % w) as f32 - cx) / scale;
+            let sy = cy + ((i / w) as f32 - cy) / scale;
+            px[c] = sample_channel(&src, w, h, c, sx, sy);
+        }
+    }
+}
+
+// Radial distortion correction: each output pixel samples the source at
+// r * (1 + k1 r^2 + k2 r^4), r = 1 at the corners. For pincushion the whole
+// map is scaled down so the corners stay inside the image.
+pub fn apply_distortion(data: &[f32], w: usize, h: usize, k1: f32, k2: f32) -> Vec<f32> {
+    if w == 0 || h == 0 {
+        return data.to_vec();
+    }
+    let cx = (w as f32 - 1.0) / 2.0;
+    let cy = (h as f32 - 1.0) / 2.0;
+    let max_r2 = (cx * cx + cy * cy).max(1.0);
+    let fit = (1.0 + k1 + k2).max(1.0);
+
+    let mut out = vec![0.0; data.len()];
+    for (i, px) in out.chunks_exact_mut(4).enumerate() {
+        let dx = (i % w) as f32 - cx;
+        let dy = (i / w) as f32 - cy;
+        let r2 = (dx * dx + dy * dy) / max_r2;
+        let scale = (1.0 + k1 * r2 + k2 * r2 * r2) / fit;
+
+        let (sx, sy) = (cx + dx * scale, cy + dy * scale);
+        for (c, value) in px.iter_mut().enumerate() {
+            *value = sample_channel(data, w, h, c, sx, sy);
+        }
+        if !in_bounds(w, h, sx, sy) {
+            px[3] = 0.0;
+        }
+    }
+    out
+}
+
+// Rotates the image `degrees` clockwise about its centre, zoomed so the
+// largest rectangle of the original aspect ratio fits inside the rotated
+// frame. Output keeps the input dimensions.
+pub fn apply_straighten(data: &[f32], w: usize, h: usize, degrees: f32) -> Vec<f32> {
+    if w == 0 || h == 0 {
+        return data.to_vec();
+    }
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (wf, hf) = (w as f32, h as f32);
+    let scale =
+        (wf / (wf * cos.abs() + hf * sin.abs())).min(hf / (wf * sin.abs() + hf * cos.abs()));
+    let cx = (wf - 1.0) / 2.0;
+    let cy = (hf - 1.0) / 2.0;
+
+    let mut out = vec![0.0; data.len()];
+    for (i, px) in out.chunks_exact_mut(4).enumerate() {
+        let dx = (i % w) as f32 - cx;
+        let dy = (i / w) as f32 - cy;
+        let sx = cx + (cos * dx + sin * dy) * scale;
+        let sy = cy + (-sin * dx + cos * dy) * scale;
+
+        for (c, value) in px.iter_mut().enumerate() {
+            *value = sample_channel(data, w, h, c, sx, sy);
+        }
+        if !in_bounds(w, h, sx, sy) {
+            px[3] = 0.0;
+        }
+    }
+    out
+}
+
+// Projective map from the unit square onto the quad p0..p3 (corners for
+// (0,0), (1,0), (1,1), (0,1)), after Heckbert. Returns [a, b, c, d, e, f, g, h]
+// with x = (a u + b v + c) / (g u + h v + 1), y = (d u + e v + f) / (...).
+fn square_to_quad(p: [(f32, f32); 4]) -> [f32; 8] {
+    let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = p;
+    let dx3 = x0 - x1 + x2 - x3;
+    let dy3 = y0 - y1 + y2 - y3;
+    if dx3.abs() < 1e-9 && dy3.abs() < 1e-9 {
+        return [x1 - x0, x2 - x1, x0, y1 - y0, y2 - y1, y0, 0.0, 0.0];
+    }
+
+    let dx1 = x1 - x2;
+    let dx2 = x3 - x2;
+    let dy1 = y1 - y2;
+    let dy2 = y3 - y2;
+    let den = dx1 * dy2 - dx2 * dy1;
+    let g = (dx3 * dy2 - dx2 * dy3) / den;
+    let h = (dx1 * dy3 - dx3 * dy1) / den;
+    [
+        x1 - x0 + g * x1,
+        x3 - x0 + h * x3,
+        x0,
+        y1 - y0 + g * y1,
+        y3 - y0 + h * y3,
+        y0,
+        g,
+        h,
+    ]
+}
+
+// Keystone correction. Each output pixel samples a source quad lying inside
+// the image (one edge pulled in), so the result is stretched to fill the
+// frame and needs no empty borders: the edges are implicitly cropped.
+pub fn apply_perspective(
+    data: &[f32],
+    w: usize,
+    h: usize,
+    vertical: f32,
+    horizontal: f32,
+) -> Vec<f32> {
+    if w == 0 || h == 0 {
+        return data.to_vec();
+    }
+    // Strongest correction pulls an edge in by 30% of the frame
+    let max_inset = 0.3 / 2.0;
+    let v = vertical.clamp(-1.0, 1.0) * max_inset;
+    let hz = horizontal.clamp(-1.0, 1.0) * max_inset;
+    let (top, bottom) = (v.max(0.0), (-v).max(0.0));
+    let (right, left) = (hz.max(0.0), (-hz).max(0.0));
+
+    let m = square_to_quad([
+        (top, left),
+        (1.0 - top, right),
+        (1.0 - bottom, 1.0 - right),
+        (bottom, 1.0 - left),
+    ]);
+
+    let mut out = vec![0.0; data.len()];
+    for (i, px) in out.chunks_exact_mut(4).enumerate() {
+        let u = (i % w) as f32 / (w - 1).max(1) as f32;
+        let t = (i / w) as f32 / (h - 1).max(1) as f32;
+        let den = m[6] * u + m[7] * t + 1.0;
+        let sx = (m[0] * u + m[1] * t + m[2]) / den * (w - 1) as f32;
+        let sy = (m[3] * u + m[4] * t + m[5]) / den * (h - 1) as f32;
+
+        for (c, value) in px.iter_mut().enumerate() {
+            *value = sample_channel(data, w, h, c, sx, sy);
+        }
+        if !in_bounds(w, h, sx, sy) {
+            px[3] = 0.0;
+        }
+    }
+    out
+}
+
+// Clarity: local contrast on luminance. The luma detail (luma - blurred luma)
+// is boosted in gamma space and weighted towards the midtones so shadows and
+// highlights are not crushed.
+pub fn apply_clarity(data: &mut [f32], w: usize, h: usize, amount: f32) {
+    let luma = luminance(data);
+    // Radius scales with the image so previews and full-res exports match
+    let sigma = w.max(h) as f32 * 0.01;
+    let blurred = blur(&luma, w, h, sigma);
+
+    for (i, px) in data.chunks_exact_mut(4).enumerate() {
+        let l = luma[i];
+        if l <= 0.0 {
+            continue;
+        }
+        let lg = l.powf(1.0 / 2.2);
+        let bg = blurred[i].max(0.0).powf(1.0 / 2.2);
+        let midtone = (4.0 * lg * (1.0 - lg)).clamp(0.0, 1.0);
+        let target = (lg + amount * midtone * (lg - bg)).max(0.0).powf(2.2);
+        let scale = target / l;
+
+        px[0] *= scale;
+        px[1] *= scale;
+        px[2] *= scale;
+    }
+}
+
+// Radial vignetting gain about the image centre: 1 + amount * r^2 with r
+// normalized to 1 at the corners
+pub fn apply_vignette(data: &mut [f32], w: usize, h: usize, amount: f32) {
+    let cx = (w as f32 - 1.0) / 2.0;
+    let cy = (h as f32 - 1.0) / 2.0;
+    let max_r2 = (cx * cx + cy * cy).max(1.0);
+
+    for (i, px) in data.chunks_exact_mut(4).enumerate() {
+        let dx = (i % w) as f32 - cx;
+        let dy = (i / w) as f32 - cy;
+        let r2 = (dx * dx + dy * dy) / max_r2;
+        let gain = (1.0 + amount * r2).max(0.0);
+
+        px[0] *= gain;
+        px[1] *= gain;
+        px[2] *= gain;
+    }
+}
+
+// Edge-preserving luminance noise reduction: a small bilateral filter on
+// gamma-encoded luma (so noise has similar amplitude across tones). Colour
+// ratios are kept, only the luminance of each pixel changes.
+pub fn apply_luma_denoise(data: &mut [f32], w: usize, h: usize, amount: f32) {
+    let amount = amount.clamp(0.0, 1.0);
+    let radius: isize = 2;
+    let sigma_spatial = 1.5_f32;
+    let sigma_range = 0.02 + 0.08 * amount;
+
+    let encoded: Vec<f32> = luminance(data)
+        .into_iter()
+        .map(|l| l.max(0.0).powf(1.0 / 2.2))
+        .collect();
+
+    let mut spatial = Vec::new();
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let d2 = (dx * dx + dy * dy) as f32;
+            spatial.push((dx, dy, (-d2 / (2.0 * sigma_spatial * sigma_spatial)).exp()));
+        }
+    }
+    let range_den = 2.0 * sigma_range * sigma_range;
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            let center = encoded[i];
+
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for &(dx, dy, ws) in &spatial {
+                let sx = (x as isize + dx).clamp(0, w as isize - 1) as usize;
+                let sy = (y as isize + dy).clamp(0, h as isize - 1) as usize;
+                let v = encoded[sy * w + sx];
+                let diff = v - center;
+                let weight = ws * (-(diff * diff) / range_den).exp();
+                sum += v * weight;
+                weight_sum += weight;
+            }
+
+            let filtered = sum / weight_sum;
+            let target = center + (filtered - center) * amount;
+            if center <= 0.0 {
+                continue;
+            }
+            let scale = (target.max(0.0) / center).powf(2.2);
+
+            let px = &mut data[i * 4..i * 4 + 4];
+            px[0] *= scale;
+            px[1] *= scale;
+            px[2] *= scale;
+        }
+    }
+}
+
+// Unsharp mask on luminance of already tone-mapped (display-encoded) data:
+// each channel gets amount * (luma - blurred luma). Results are clamped to 0..1.
+pub fn apply_unsharp_mask(data: &mut [f32], w: usize, h: usize, amount: f32, sigma: f32) {
+    if amount <= 0.0 {
+        return;
+    }
+    let luma = luminance(data);
+    let blurred = blur(&luma, w, h, sigma);
+
+    for (i, px) in data.chunks_exact_mut(4).enumerate() {
+        let detail = amount * (luma[i] - blurred[i]);
+        px[0] = (px[0] + detail).clamp(0.0, 1.0);
+        px[1] = (px[1] + detail).clamp(0.0, 1.0);
+        px[2] = (px[2] + detail).clamp(0.0, 1.0);
+    }
+}
+
+// Deterministic per-pixel noise in -1..1 (splitmix64 of seed and index)
+fn hash_noise(seed: u64, i: u64) -> f32 {
+    let mut z = seed.wrapping_add(i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+// Film grain on display-encoded data. Noise is blurred to the grain size,
+// renormalized, and weighted towards the midtones like real film.
+pub fn apply_grain(data: &mut [f32], w: usize, h: usize, amount: f32, size: f32, seed: u64) {
+    if amount <= 0.0 || w == 0 || h == 0 {
+        return;
+    }
+    let noise: Vec<f32> = (0..w * h).map(|i| hash_noise(seed, i as u64)).collect();
+    let mut grain = blur(&noise, w, h, size.max(0.0));
+
+    // Blurring lowers the amplitude; bring it back to unit deviation
+    let variance = grain.iter().map(|v| v * v).sum::<f32>() / grain.len() as f32;
+    if variance > 0.0 {
+        let norm = 1.0 / variance.sqrt();
+        grain.iter_mut().for_each(|v| *v *= norm);
+    }
+
+    let strength = amount * 0.1;
+    for (i, px) in data.chunks_exact_mut(4).enumerate() {
+        let l = (0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2]).clamp(0.0, 1.0);
+        let offset = grain[i] * strength * 4.0 * l * (1.0 - l);
+        px[0] = (px[0] + offset).clamp(0.0, 1.0);
+        px[1] = (px[1] + offset).clamp(0.0, 1.0);
+        px[2] = (px[2] + offset).clamp(0.0, 1.0);
+    }
+}
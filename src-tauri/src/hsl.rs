@@ -0,0 +1,255 @@
+use crate::oklab;
+use serde::{Deserialize, Serialize};
+
+// Offsets for one colour band. hue is in degrees, saturation and luminance
+// are -1..1 (saturation scales chroma, luminance is +/- 1 EV on that band).
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+pub struct HslBand {
+    #[serde(default)]
+    pub hue: f32,
+    #[serde(default)]
+    pub saturation: f32,
+    #[serde(default)]
+    pub luminance: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct HslAdjustments {
+    pub red: HslBand,
+    pub orange: HslBand,
+    pub yellow: HslBand,
+    pub green: HslBand,
+    pub aqua: HslBand,
+    pub blue: HslBand,
+    pub purple: HslBand,
+    pub magenta: HslBand,
+}
+
+// Band centres in degrees, in the same order as `bands()`
+const BAND_HUES: [f32; 8] = [0.0, 30.0, 60.0, 120.0, 180.0, 240.0, 270.0, 300.0];
+
+impl HslAdjustments {
+    fn bands(&self) -> [HslBand; 8] {
+        [
+            self.red,
+            self.orange,
+            self.yellow,
+            self.green,
+            self.aqua,
+            self.blue,
+            self.purple,
+            self.magenta,
+        ]
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == HslAdjustments::default()
+    }
+
+    // Offsets for a hue, linearly blended between the two nearest band centres
+    fn offsets_at(&self, hue: f32) -> HslBand {
+        let bands = self.bands();
+        let n = BAND_HUES.len();
+
+        for i in 0..n {
+            let start = BAND_HUES[i];
+            let end = if i + 1 < n { BAND_HUES[i + 1] } else { 360.0 };
+            if hue >= start && hue < end {
+                let t = (hue - start) / (end - start);
+                let a = bands[i];
+                let b = bands[(i + 1) % n];
+                return HslBand {
+                    hue: a.hue + (b.hue - a.hue) * t,
+                    saturation: a.saturation + (b.saturation - a.saturation) * t,
+                    luminance: a.luminance + (b.luminance - a.luminance) * t,
+                };
+            }
+        }
+        bands[0]
+    }
+}
+
+// Hue in degrees (0..360) and saturation (chroma / max) of a linear RGB triple
+fn hue_sat(rgb: &[f32; 3]) -> Option<(f32, f32)> {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    let min = rgb[0].min(rgb[1]).min(rgb[2]);
+    let delta = max - min;
+    if max <= 0.0 || delta <= 1e-6 {
+        return None;
+    }
+
+    let h = if max == rgb[0] {
+        60.0 * ((rgb[1] - rgb[2]) / delta)
+    } else if max == rgb[1] {
+        60.0 * ((rgb[2] - rgb[0]) / delta + 2.0)
+    } else {
+        60.0 * ((rgb[0] - rgb[1]) / delta + 4.0)
+    };
+
+    Some((h.rem_euclid(360.0), (delta / max).clamp(0.0, 1.0)))
+}
+
+// Fully saturated colour (HSV with s = v = 1) for a hue in degrees
+pub fn hue_to_rgb(hue: f32) -> [f32; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    match h as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+fn luma(rgb: &[f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+// Rotates a colour around the grey axis by `degrees`, then rescales it to
+// its original luma (the rotation alone keeps R + G + B, so a red turned
+// green would brighten)
+fn rotate_hue(rgb: &mut [f32; 3], degrees: f32) {
+    let before = luma(rgb);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let k = (1.0 - cos) / 3.0;
+    let s = sin / 3.0_f32.sqrt();
+
+    let a = cos + k;
+    let b = k - s;
+    let c = k + s;
+
+    let [r, g, bl] = *rgb;
+    rgb[0] = a * r + b * g + c * bl;
+    rgb[1] = c * r + a * g + b * bl;
+    rgb[2] = b * r + c * g + a * bl;
+
+    let after = luma(rgb);
+    if after > 1e-6 {
+        let scale = before / after;
+        rgb.iter_mut().for_each(|c| *c *= scale);
+    }
+}
+
+pub fn apply_hsl(rgb: &mut [f32; 3], adjustments: &HslAdjustments) {
+    let Some((hue, sat)) = hue_sat(rgb) else {
+        return; // neutral pixels have no hue to target
+    };
+    let offsets = adjustments.offsets_at(hue);
+
+    if offsets.hue != 0.0 {
+        rotate_hue(rgb, offsets.hue);
+    }
+
+    if offsets.saturation != 0.0 {
+        let l = luma(rgb);
+        let mult = 1.0 + offsets.saturation;
+        rgb[0] = l + (rgb[0] - l) * mult;
+        rgb[1] = l + (rgb[1] - l) * mult;
+        rgb[2] = l + (rgb[2] - l) * mult;
+    }
+
+    // Weighted by saturation so near-greys are barely touched
+    if offsets.luminance != 0.0 {
+        let gain = 2.0_f32.powf(offsets.luminance * sat);
+        rgb[0] *= gain;
+        rgb[1] *= gain;
+        rgb[2] *= gain;
+    }
+}
+
+// apply_hsl with the hue and saturation offsets done in OKLab, so hue shifts
+// keep lightness and saturation changes keep hue. Bands are still selected
+// by the RGB hue so they match the non-OKLab mode.
+pub fn apply_hsl_oklab(rgb: &mut [f32; 3], adjustments: &HslAdjustments) {
+    let Some((hue, sat)) = hue_sat(rgb) else {
+        return;
+    };
+    let offsets = adjustments.offsets_at(hue);
+
+    if offsets.hue != 0.0 || offsets.saturation != 0.0 {
+        oklab::adjust(rgb, 1.0 + offsets.saturation, offsets.hue);
+    }
+
+    if offsets.luminance != 0.0 {
+        let gain = 2.0_f32.powf(offsets.luminance * sat);
+        rgb[0] *= gain;
+        rgb[1] *= gain;
+        rgb[2] *= gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(hue: f32) -> HslBand {
+        HslBand {
+            hue,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn magenta_blends_into_red_across_360() {
+        let adjustments = HslAdjustments {
+            magenta: band(20.0),
+            red: band(-10.0),
+            ..Default::default()
+        };
+        assert_eq!(adjustments.offsets_at(300.0).hue, 20.0);
+        assert!((adjustments.offsets_at(330.0).hue - 5.0).abs() < 1e-4);
+        assert!((adjustments.offsets_at(359.9).hue + 9.95).abs() < 1e-2);
+        assert_eq!(adjustments.offsets_at(0.0).hue, -10.0);
+        // Red blends towards orange on the other side
+        assert_eq!(adjustments.offsets_at(15.0).hue, -5.0);
+    }
+
+    #[test]
+    fn neutral_colours_pass_through() {
+        let all = HslBand {
+            hue: 40.0,
+            saturation: 0.5,
+            luminance: 1.0,
+        };
+        let adjustments = HslAdjustments {
+            red: all,
+            orange: all,
+            yellow: all,
+            green: all,
+            aqua: all,
+            blue: all,
+            purple: all,
+            magenta: all,
+        };
+        for grey in [[0.0; 3], [0.18; 3], [1.0; 3]] {
+            let mut rgb = grey;
+            apply_hsl(&mut rgb, &adjustments);
+            assert_eq!(rgb, grey);
+            apply_hsl_oklab(&mut rgb, &adjustments);
+            assert_eq!(rgb, grey);
+        }
+
+        // No adjustments leave colours alone
+        let mut rgb = [0.6, 0.2, 0.1];
+        apply_hsl(&mut rgb, &HslAdjustments::default());
+        assert_eq!(rgb, [0.6, 0.2, 0.1]);
+    }
+
+    #[test]
+    fn hue_rotation_keeps_luma() {
+        let adjustments = HslAdjustments {
+            red: band(60.0),
+            blue: band(-45.0),
+            ..Default::default()
+        };
+        for colour in [[0.6, 0.1, 0.1], [0.1, 0.15, 0.7], [0.5, 0.3, 0.35]] {
+            let mut rgb = colour;
+            apply_hsl(&mut rgb, &adjustments);
+            assert!(rgb != colour);
+            assert!((luma(&rgb) - luma(&colour)).abs() < 1e-5);
+        }
+    }
+}
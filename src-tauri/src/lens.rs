@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+// Radial distortion profile, matched against the lens model string from the
+// file's metadata. Coefficients follow the model
+// r_source = r * (1 + k1 r^2 + k2 r^4), with r normalized to 1 at the corners
+// (negative k1 = barrel, positive = pincushion). A few common lenses are
+// built in; user profiles (see set_lens_profiles) take precedence for any
+// lens they match. Zooms get one entry per measured focal length.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LensProfile {
+    // Matched case-insensitively as a substring of the lens model
+    pub lens: String,
+    // mm; None for a prime measured once
+    #[serde(default)]
+    pub focal_length: Option<f32>,
+    pub k1: f32,
+    #[serde(default)]
+    pub k2: f32,
+}
+
+static PROFILES: Mutex<Vec<LensProfile>> = Mutex::new(Vec::new());
+
+// (lens, focal length, k1, k2): typical values for these models, barrel at
+// the wide end of the zooms turning into mild pincushion at the long end
+const BUILT_IN: &[(&str, Option<f32>, f32, f32)] = &[
+    ("XF16mmF1.4", None, -0.035, 0.004),
+    ("XF18-55mmF2.8-4", Some(18.0), -0.060, 0.010),
+    ("XF18-55mmF2.8-4", Some(35.0), -0.010, 0.000),
+    ("XF18-55mmF2.8-4", Some(55.0), 0.012, 0.000),
+    ("FE 24-70mm F2.8 GM", Some(24.0), -0.055, 0.012),
+    ("FE 24-70mm F2.8 GM", Some(35.0), -0.005, 0.000),
+    ("FE 24-70mm F2.8 GM", Some(70.0), 0.020, -0.002),
+    ("EF50mm f/1.8 STM", None, -0.010, 0.000),
+    ("RF35mm F1.8", None, -0.040, 0.003),
+    ("NIKKOR Z 24-70mm f/4 S", Some(24.0), -0.070, 0.015),
+    ("NIKKOR Z 24-70mm f/4 S", Some(50.0), 0.010, 0.000),
+    ("NIKKOR Z 24-70mm f/4 S", Some(70.0), 0.018, -0.002),
+];
+
+fn built_in() -> Vec<LensProfile> {
+    BUILT_IN
+        .iter()
+        .map(|&(lens, focal_length, k1, k2)| LensProfile {
+            lens: lens.into(),
+            focal_length,
+            k1,
+            k2,
+        })
+        .collect()
+}
+
+pub fn validate(profiles: &[LensProfile]) -> Result<(), String> {
+    for p in profiles {
+        if p.lens.trim().is_empty() {
+            return Err("Lens profile without a lens name".into());
+        }
+        if !p.k1.is_finite() || !p.k2.is_finite() {
+            return Err(format!("Invalid coefficients for {}", p.lens));
+        }
+        if p.focal_length.is_some_and(|f| !f.is_finite() || f <= 0.0) {
+            return Err(format!("Invalid focal length for {}", p.lens));
+        }
+    }
+    Ok(())
+}
+
+pub fn set_profiles(profiles: Vec<LensProfile>) {
+    *PROFILES.lock().unwrap() = profiles;
+}
+
+// (k1, k2) for a known lens at the given focal length, interpolated between
+// the nearest measured focal lengths and held at the ends of the range. None
+// (no correction) for unknown lenses, or a zoom when the focal length isn't
+// recorded.
+pub fn distortion_for(lens: &str, focal_length: Option<f32>) -> Option<(f32, f32)> {
+    let user = PROFILES.lock().unwrap();
+    distortion_in(&user, lens, focal_length)
+        .or_else(|| distortion_in(&built_in(), lens, focal_length))
+}
+
+fn distortion_in(
+    profiles: &[LensProfile],
+    lens: &str,
+    focal_length: Option<f32>,
+) -> Option<(f32, f32)> {
+    let lens = lens.to_ascii_lowercase();
+    let matching: Vec<&LensProfile> = profiles
+        .iter()
+        .filter(|p| lens.contains(&p.lens.to_ascii_lowercase()))
+        .collect();
+
+    let mut measured: Vec<(f32, f32, f32)> = matching
+        .iter()
+        .filter_map(|p| p.focal_length.map(|f| (f, p.k1, p.k2)))
+        .collect();
+    if measured.is_empty() {
+        return matching.first().map(|p| (p.k1, p.k2));
+    }
+    let focal = focal_length?;
+    measured.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let above = measured.partition_point(|&(f, _, _)| f < focal);
+    if above == 0 {
+        let (_, k1, k2) = measured[0];
+        return Some((k1, k2));
+    }
+    if above == measured.len() {
+        let (_, k1, k2) = measured[measured.len() - 1];
+        return Some((k1, k2));
+    }
+    let (f0, k1_0, k2_0) = measured[above - 1];
+    let (f1, k1_1, k2_1) = measured[above];
+    let t = (focal - f0) / (f1 - f0);
+    Some((k1_0 + (k1_1 - k1_0) * t, k2_0 + (k2_1 - k2_0) * t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(lens: &str, focal_length: Option<f32>, k1: f32) -> LensProfile {
+        LensProfile {
+            lens: lens.into(),
+            focal_length,
+            k1,
+            k2: 0.0,
+        }
+    }
+
+    #[test]
+    fn matches_model_substring_ignoring_case() {
+        let profiles = [profile("XF16mmF1.4", None, -0.035)];
+        let found = distortion_in(&profiles, "Fujifilm XF16mmF1.4 R WR", None);
+        assert_eq!(found, Some((-0.035, 0.0)));
+        assert_eq!(
+            distortion_in(&profiles, "xf16mmf1.4 r wr", Some(16.0)),
+            found
+        );
+        assert_eq!(distortion_in(&profiles, "XF23mmF1.4 R", None), None);
+    }
+
+    #[test]
+    fn zoom_interpolates_and_holds_at_the_ends() {
+        let profiles = [
+            profile("Zoom 24-70", Some(24.0), -0.06),
+            profile("Zoom 24-70", Some(70.0), 0.02),
+        ];
+        let k1 = |focal| distortion_in(&profiles, "Zoom 24-70", focal).map(|(k1, _)| k1);
+        assert!((k1(Some(47.0)).unwrap() + 0.02).abs() < 1e-6);
+        assert_eq!(k1(Some(16.0)), Some(-0.06));
+        assert_eq!(k1(Some(100.0)), Some(0.02));
+        // A zoom without a recorded focal length is left alone
+        assert_eq!(k1(None), None);
+    }
+
+    #[test]
+    fn unknown_lenses_fall_back_to_no_correction() {
+        assert!(distortion_for("EF50mm f/1.8 STM", None).is_some());
+        assert!(distortion_for("Some Unknown 35mm", Some(35.0)).is_none());
+    }
+}
@@ -1,24 +1,152 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use image::{ImageBuffer, Rgb};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{ImageBuffer, Rgb, Rgba};
 use serde::Serialize;
-use std::ffi::CString;
+use std::borrow::Cow;
+use std::ffi::{c_char, CStr, CString};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
 use std::ptr;
-use std::sync::Mutex;
-use tauri::State;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::ipc::Response;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tiff::decoder::Decoder as TiffDecoder;
+use tiff::encoder::{colortype, DirectoryEncoder, Rational, TiffEncoder, TiffKind};
+use tiff::tags::Tag;
+
+mod dng;
+mod error;
+mod filters;
+mod hsl;
+mod lens;
+mod local;
+mod lut;
+mod oklab;
+mod wb;
+mod xmp;
+
+use error::AppError;
+use hsl::HslAdjustments;
+use local::{GradientFilter, RadialFilter};
+
+// libraw is linked as the non-thread-safe build: every libraw_init ..
+// libraw_close section holds this, whichever command it runs for
+static LIBRAW: Mutex<()> = Mutex::new(());
 
 struct AppState {
     preview_context: Mutex<Option<PreviewContext>>,
+    // Source file of the loaded preview
+    current_path: Mutex<Option<String>>,
+    // Frames load_raw_merge averaged into the preview, current_path first;
+    // empty for a single file. Full-resolution decodes average them too.
+    merge_paths: Mutex<Vec<String>>,
+    // Last full-quality decode, keyed by source path and decode options
+    full_res_cache: Mutex<Option<(String, DecodeOptions, Arc<PreviewContext>)>>,
+    params_history: Mutex<ParamsHistory>,
+    // Bumped by every load_raw; a load whose number is no longer current
+    // has been superseded and drops its result
+    load_generation: AtomicU64,
+    // Preview loads run one at a time, so a queued load that has been
+    // superseded can be skipped before it decodes
+    decode_lock: Mutex<()>,
+    // Bumped by every export, which keeps its number. cancel_export records
+    // the latest number: exports up to it stop at the next row, ones started
+    // after the cancel aren't affected.
+    export_generation: AtomicU64,
+    export_cancelled: AtomicU64,
+}
+
+const MAX_HISTORY: usize = 100;
+
+// Undo/redo stack of parameter snapshots. `cursor` indexes the current state;
+// entries after it are the redo tail.
+#[derive(Default)]
+struct ParamsHistory {
+    entries: Vec<ImageParams>,
+    cursor: usize,
+}
+
+impl ParamsHistory {
+    fn push(&mut self, params: ImageParams) {
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.cursor + 1);
+        }
+        self.entries.push(params);
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.remove(0);
+        }
+        self.cursor = self.entries.len() - 1;
+    }
+
+    fn undo(&mut self) -> Option<ImageParams> {
+        if self.cursor == 0 {
+            return None;
+        }
+        self.cursor -= 1;
+        self.entries.get(self.cursor).cloned()
+    }
+
+    fn redo(&mut self) -> Option<ImageParams> {
+        if self.cursor + 1 >= self.entries.len() {
+            return None;
+        }
+        self.cursor += 1;
+        self.entries.get(self.cursor).cloned()
+    }
 }
 
+#[derive(Clone)]
 struct PreviewContext {
     width: u32,
     height: u32,
     data: Vec<f32>, // RGB interleaved
+    exif: ExifInfo,
+    orientation: u16, // camera orientation in clockwise degrees, not yet applied to data
+    // libraw had no camera matrix and used the raw colours as-is, so the
+    // colours are probably wrong
+    color_profile_missing: bool,
+    // Camera's default exposure offset (DNG BaselineExposure, EV), already
+    // applied to data
+    baseline_exposure: f32,
+    // As Shot was requested but the file had no usable camera WB, so the
+    // daylight multipliers were used
+    wb_estimated: bool,
+    // data was balanced with libraw's daylight multipliers (Custom mode, or
+    // the As Shot fallback above) rather than the camera's, so temperature
+    // and tint measured on it are absolute
+    daylight_wb: bool,
+    // Manufacturer vignetting correction from the file, matching the data's
+    // current orientation. Applied in prepare_buffer so it can be toggled.
+    vignette_profile: Option<dng::VignetteProfile>,
+    // Camera white balance as (temperature, tint), if the file records one
+    as_shot_wb: Option<(f32, f32)>,
+    // No CFA (e.g. Leica Monochrom): data is grey, with no colour to balance
+    monochrome_sensor: bool,
+    // Set when data is only this display-space part of the frame (a load_raw
+    // crop). Whole-frame passes and analysis don't apply to it.
+    roi: Option<CropRect>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Serialize, Clone, Default)]
+struct ExifInfo {
+    make: Option<String>,
+    model: Option<String>,
+    lens: Option<String>,
+    iso: Option<f32>,
+    shutter: Option<f32>, // seconds
+    aperture: Option<f32>,
+    focal_length: Option<f32>, // mm
+    timestamp: Option<i64>,    // unix seconds
+}
+
+// remote = "Self": the derives become inherent functions, wrapped by the
+// trait impls below (for the legacy wb_mode default)
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(remote = "Self")]
 struct ImageParams {
     exposure: f32,
     contrast: f32,
@@ -29,27 +157,805 @@ struct ImageParams {
     whites: f32,
     blacks: f32,
     saturation: f32,
+    // Added after the first release; default keeps old params files loading
+    #[serde(default)]
+    vibrance: f32,
+    #[serde(default)]
+    crop: Option<CropRect>,
+    // Clockwise degrees (0/90/180/270); None uses the camera orientation,
+    // or the sensor layout when auto_orient is off (see display_rotation)
+    #[serde(default)]
+    rotation: Option<u16>,
+    // Follow the camera's orientation flag when no explicit rotation is set
+    #[serde(default = "default_auto_orient")]
+    auto_orient: bool,
+    // Tone curve control points (input, output) in 0..1 display space,
+    // sorted by input. Fewer than two points means identity.
+    #[serde(default, deserialize_with = "deserialize_curve")]
+    curve: Vec<(f32, f32)>,
+    #[serde(default)]
+    clarity: f32,
+    #[serde(default)]
+    hsl: HslAdjustments,
+    // Radial gain 1 + v * r^2; positive brightens corners, negative darkens
+    #[serde(default)]
+    vignette_correction: f32,
+    // Luminance noise reduction strength, 0..1
+    #[serde(default)]
+    noise_reduction: f32,
+    // Split toning: hue in degrees, saturation 0..1, balance -1..1
+    // (positive favours the highlight tint)
+    #[serde(default)]
+    shadow_hue: f32,
+    #[serde(default)]
+    shadow_sat: f32,
+    #[serde(default)]
+    highlight_hue: f32,
+    #[serde(default)]
+    highlight_sat: f32,
+    #[serde(default)]
+    balance: f32,
+    #[serde(default)]
+    wb_mode: WbMode,
+    // Export-only unsharp mask on the final image. Radius is the blur sigma
+    // in output pixels; 0 falls back to 1.
+    #[serde(default)]
+    sharpen_amount: f32,
+    #[serde(default)]
+    sharpen_radius: f32,
+    // Black & white conversion: grey = weighted channel sum, weights are
+    // normalized. All zero falls back to Rec.709 luminance.
+    #[serde(default)]
+    monochrome: bool,
+    #[serde(default)]
+    bw_red: f32,
+    #[serde(default)]
+    bw_green: f32,
+    #[serde(default)]
+    bw_blue: f32,
+    // Per-channel linear gains, applied after white balance
+    #[serde(default = "unit_gain")]
+    red_gain: f32,
+    #[serde(default = "unit_gain")]
+    green_gain: f32,
+    #[serde(default = "unit_gain")]
+    blue_gain: f32,
+    // Bring out-of-gamut colours back by desaturating towards grey of the
+    // same luminance instead of clamping each channel (which shifts hue)
+    #[serde(default)]
+    gamut_compress: bool,
+    // Lateral chromatic aberration: radial scale of the red and blue
+    // channels relative to green about the image centre
+    #[serde(default = "unit_scale")]
+    ca_red_scale: f32,
+    #[serde(default = "unit_scale")]
+    ca_blue_scale: f32,
+    // Export-only film grain: strength (0 = off) and grain size in output pixels
+    #[serde(default)]
+    grain_amount: f32,
+    #[serde(default)]
+    grain_size: f32,
+    // Keystone correction, -1..1. Positive vertical widens the top (fixes
+    // converging verticals), positive horizontal widens the right side.
+    #[serde(default)]
+    perspective_vertical: f32,
+    #[serde(default)]
+    perspective_horizontal: f32,
+    // Distortion correction from the user's lens profiles (lens.rs,
+    // set_lens_profiles); unknown lenses are left as shot
+    #[serde(default)]
+    lens_correction: bool,
+    // Vignetting correction recorded by the camera / converter (DNG opcode),
+    // applied automatically when the file has one
+    #[serde(default = "default_profile_vignetting")]
+    profile_vignetting: bool,
+    // Width of the luminance band the shadows / highlights sliders act on:
+    // shadows fade out at shadow_range, highlights fade in from
+    // 1 - highlight_range. Smaller values target only the extremes.
+    #[serde(default = "default_tone_range")]
+    shadow_range: f32,
+    #[serde(default = "default_tone_range")]
+    highlight_range: f32,
+    // Fine rotation in degrees (clockwise) for levelling horizons. The frame
+    // is zoomed to the largest same-aspect rectangle so no corners are empty.
+    #[serde(default)]
+    straighten_angle: f32,
+    // Graduated filters (local adjustments with a linear falloff)
+    #[serde(default)]
+    gradients: Vec<GradientFilter>,
+    // Elliptical masks for selective adjustments
+    #[serde(default)]
+    radial_filters: Vec<RadialFilter>,
+    // .cube 3D LUT applied to the final display-encoded RGB
+    #[serde(default)]
+    apply_lut: Option<String>,
+    // Encoding curve for sRGB output
+    #[serde(default)]
+    transfer_curve: TransferCurve,
+    // Adjustment layers composited in order on top of these params
+    #[serde(default)]
+    layers: Vec<Layer>,
+    // Highlight rolloff before encoding; None clips at 1 as before
+    #[serde(default)]
+    tone_mapping: ToneMapping,
+    // Space the saturation, vibrance and HSL steps work in
+    #[serde(default)]
+    adjustment_space: AdjustmentSpace,
+    // Scanned colour negative: inverted against the film base (the orange
+    // mask's linear RGB, see sample_film_base) before everything else
+    #[serde(default)]
+    film_negative: bool,
+    #[serde(default = "default_film_base")]
+    film_base: [f32; 3],
+}
+
+// Rgb is the original behaviour (mixing towards Rec.709 luminance, which
+// can drift hue when saturating strongly); Oklab scales perceptual chroma
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum AdjustmentSpace {
+    #[default]
+    Rgb,
+    Oklab,
+}
+
+// Where a layer applies. Only whole-image layers so far.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum MaskSpec {
+    #[default]
+    Global,
+}
+
+// An adjustment layer: its per-pixel adjustments (steps 1-8 of
+// apply_processing) run on the result of the layers below and are mixed in
+// by opacity. Geometry, spatial filters, output and nested layers in
+// `params` are ignored.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+struct Layer {
+    params: ImageParams,
+    #[serde(default)]
+    mask: MaskSpec,
+    #[serde(default = "unit_gain")]
+    opacity: f32,
+}
+
+// Curve points as eval_curve needs them, whatever order the params file,
+// preset or pasted settings had: finite, sorted by input, one point per input
+// (the first one listed wins)
+fn deserialize_curve<'de, D>(deserializer: D) -> Result<Vec<(f32, f32)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut points = <Vec<(f32, f32)> as serde::Deserialize>::deserialize(deserializer)?;
+    points.retain(|p| p.0.is_finite() && p.1.is_finite());
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points.dedup_by(|next, prev| next.0 == prev.0);
+    Ok(points)
+}
+
+fn default_auto_orient() -> bool {
+    true
+}
+
+fn default_profile_vignetting() -> bool {
+    true
+}
+
+fn default_film_base() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn unit_gain() -> f32 {
+    1.0
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+fn default_tone_range() -> f32 {
+    0.6
+}
+
+impl ImageParams {
+    fn has_perspective(&self) -> bool {
+        self.perspective_vertical != 0.0 || self.perspective_horizontal != 0.0
+    }
+
+    // Spatial settings defined in the displayed orientation, which must be
+    // applied after the 90 degree rotation
+    fn needs_display_orientation(&self) -> bool {
+        self.has_perspective() || self.has_local_adjustments()
+    }
+
+    fn has_local_adjustments(&self) -> bool {
+        !self.gradients.is_empty() || !self.radial_filters.is_empty()
+    }
+
+    // The parsed LUT (cached across calls), if one is set
+    fn lut(&self) -> Result<Option<Arc<lut::Lut3d>>, String> {
+        self.apply_lut.as_deref().map(lut::load).transpose()
+    }
+
+    // The params as they apply to ctx: a monochrome sensor has no colour to
+    // balance, so Custom temperature/tint (from a loaded or pasted edit) would
+    // only tint the grey image. Layers included.
+    fn for_context(&self, ctx: &PreviewContext) -> Cow<'_, ImageParams> {
+        if !ctx.monochrome_sensor {
+            return Cow::Borrowed(self);
+        }
+        let mut params = self.clone();
+        params.wb_mode = WbMode::AsShot;
+        for layer in &mut params.layers {
+            layer.params.wb_mode = WbMode::AsShot;
+        }
+        Cow::Owned(params)
+    }
+}
+
+impl serde::Serialize for ImageParams {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ImageParams::serialize(self, serializer)
+    }
+}
+
+// Params saved before wb_mode existed always applied temperature/tint, so a
+// non-neutral balance without wb_mode loads as Custom rather than AsShot
+impl<'de> serde::Deserialize<'de> for ImageParams {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = <serde_json::Value as serde::Deserialize>::deserialize(deserializer)?;
+        let has_wb_mode = value.get("wb_mode").is_some();
+        let mut params = ImageParams::deserialize(value).map_err(serde::de::Error::custom)?;
+        if !has_wb_mode && (params.temperature != 5500.0 || params.tint != 0.0) {
+            params.wb_mode = WbMode::Custom;
+        }
+        Ok(params)
+    }
+}
+
+// Neutral edit: apply_processing leaves pixels unchanged apart from the
+// output gamma. 5500K / tint 0 is the custom-WB reference (unit gains).
+impl Default for ImageParams {
+    fn default() -> Self {
+        ImageParams {
+            exposure: 0.0,
+            contrast: 0.0,
+            temperature: 5500.0,
+            tint: 0.0,
+            highlights: 0.0,
+            shadows: 0.0,
+            whites: 0.0,
+            blacks: 0.0,
+            saturation: 0.0,
+            vibrance: 0.0,
+            crop: None,
+            rotation: None,
+            auto_orient: true,
+            curve: Vec::new(),
+            clarity: 0.0,
+            hsl: HslAdjustments::default(),
+            vignette_correction: 0.0,
+            noise_reduction: 0.0,
+            shadow_hue: 0.0,
+            shadow_sat: 0.0,
+            highlight_hue: 0.0,
+            highlight_sat: 0.0,
+            balance: 0.0,
+            wb_mode: WbMode::AsShot,
+            sharpen_amount: 0.0,
+            sharpen_radius: 0.0,
+            monochrome: false,
+            bw_red: 0.0,
+            bw_green: 0.0,
+            bw_blue: 0.0,
+            red_gain: unit_gain(),
+            green_gain: unit_gain(),
+            blue_gain: unit_gain(),
+            gamut_compress: false,
+            ca_red_scale: unit_scale(),
+            ca_blue_scale: unit_scale(),
+            grain_amount: 0.0,
+            grain_size: 0.0,
+            perspective_vertical: 0.0,
+            perspective_horizontal: 0.0,
+            lens_correction: false,
+            profile_vignetting: default_profile_vignetting(),
+            shadow_range: default_tone_range(),
+            highlight_range: default_tone_range(),
+            straighten_angle: 0.0,
+            gradients: Vec::new(),
+            radial_filters: Vec::new(),
+            apply_lut: None,
+            transfer_curve: TransferCurve::default(),
+            layers: Vec::new(),
+            tone_mapping: ToneMapping::default(),
+            adjustment_space: AdjustmentSpace::default(),
+            film_negative: false,
+            film_base: default_film_base(),
+        }
+    }
+}
+
+// sRGB encoding: the exact IEC 61966-2-1 curve (linear toe below 0.0031308)
+// or a pure 2.2 power, which lifts the deepest shadows slightly. The other
+// output spaces are defined as pure power curves and ignore this.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum TransferCurve {
+    #[default]
+    Srgb,
+    Gamma22,
+}
+
+// Per-channel curve taking linear 0..inf into 0..1:
+// - Reinhard: extended Reinhard, reaching white at REINHARD_WHITE
+// - Aces: Narkowicz's fit of the ACES filmic curve (adds some contrast)
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum ToneMapping {
+    #[default]
+    None,
+    Reinhard,
+    Aces,
+}
+
+const REINHARD_WHITE: f32 = 4.0;
+
+impl ToneMapping {
+    fn apply(self, v: f32) -> f32 {
+        let v = v.max(0.0);
+        match self {
+            ToneMapping::None => v,
+            ToneMapping::Reinhard => v * (1.0 + v / (REINHARD_WHITE * REINHARD_WHITE)) / (1.0 + v),
+            ToneMapping::Aces => {
+                ((v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)).min(1.0)
+            }
+        }
+    }
+}
+
+fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_decode(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Output colour space of an export. Selects libraw's output primaries and
+// the matching encoding gamma.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum OutputSpace {
+    #[default]
+    Srgb,
+    AdobeRgb,
+    ProPhoto,
+}
+
+impl OutputSpace {
+    // libraw params.output_color
+    fn libraw_code(self) -> i32 {
+        match self {
+            OutputSpace::Srgb => 1,
+            OutputSpace::AdobeRgb => 2,
+            OutputSpace::ProPhoto => 4,
+        }
+    }
+
+    fn gamma(self) -> f32 {
+        match self {
+            OutputSpace::Srgb => 2.2,
+            OutputSpace::AdobeRgb => 563.0 / 256.0,
+            OutputSpace::ProPhoto => 1.8,
+        }
+    }
+
+    // CIE xy of the white point and the red, green and blue primaries
+    fn chromaticities(self) -> [(f32, f32); 4] {
+        match self {
+            OutputSpace::Srgb => [(0.3127, 0.3290), (0.64, 0.33), (0.30, 0.60), (0.15, 0.06)],
+            OutputSpace::AdobeRgb => [(0.3127, 0.3290), (0.64, 0.33), (0.21, 0.71), (0.15, 0.06)],
+            OutputSpace::ProPhoto => [
+                (0.3457, 0.3585),
+                (0.7347, 0.2653),
+                (0.1596, 0.8404),
+                (0.0366, 0.0001),
+            ],
+        }
+    }
+}
+
+// How white balance is decided:
+// - AsShot: the camera's recorded WB multipliers are applied at decode time
+//   and temperature/tint are ignored.
+// - Custom: the decode uses libraw's daylight multipliers instead, and
+//   temperature/tint (5500K / 0 = daylight) alone set the balance.
+// Preview and export decode with the same mode, so colours match.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum WbMode {
+    #[default]
+    AsShot,
+    Custom,
+}
+
+// Settings that change the decoded linear data (and so the cache key)
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DecodeOptions {
+    space: OutputSpace,
+    wb_mode: WbMode,
+    demosaic: DemosaicQuality,
+}
+
+// Interpolation for full-quality decodes (previews pick their own):
+// - Superpixel: each 2x2 CFA block becomes one pixel, half resolution
+// - Bilinear: fast, soft, some colour fringing at edges
+// - Vng: variable number of gradients, fewer colour artifacts at edges
+// - Ahd: adaptive homogeneity-directed, sharpest (default)
+// - AhdLowNoise: AHD followed by median passes on the colour differences
+//   (R-G, B-G), which removes colour speckle at very high ISO without
+//   touching luminance detail
+// X-Trans files use libraw's own interpolation for anything but Superpixel.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum DemosaicQuality {
+    Superpixel,
+    Bilinear,
+    Vng,
+    #[default]
+    Ahd,
+    AhdLowNoise,
+}
+
+// 3x3 median passes over the colour differences for AhdLowNoise
+const CHROMA_MEDIAN_PASSES: i32 = 3;
+
+// Normalized (0..1) crop rectangle relative to the full image
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy)]
+struct CropRect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+// Maps a normalized rect in the displayed orientation (after rotating the
+// sensor image `degrees` clockwise) back to normalized sensor coordinates
+fn display_rect_to_source(rect: &CropRect, degrees: u16) -> CropRect {
+    let to_source = |u: f32, v: f32| match degrees {
+        90 => (v, 1.0 - u),
+        180 => (1.0 - u, 1.0 - v),
+        270 => (1.0 - v, u),
+        _ => (u, v),
+    };
+    let (ax, ay) = to_source(rect.x, rect.y);
+    let (bx, by) = to_source(rect.x + rect.width, rect.y + rect.height);
+    CropRect {
+        x: ax.min(bx),
+        y: ay.min(by),
+        width: (ax - bx).abs(),
+        height: (ay - by).abs(),
+    }
+}
+
+// Maps an optional normalized crop onto a w x h image, returning (x, y, width, height)
+// in pixels. Always yields at least a 1x1 region inside the image.
+fn crop_to_pixels(crop: Option<&CropRect>, w: u32, h: u32) -> (u32, u32, u32, u32) {
+    let Some(c) = crop else {
+        return (0, 0, w, h);
+    };
+
+    let x0 = (c.x.clamp(0.0, 1.0) * w as f32).round() as u32;
+    let y0 = (c.y.clamp(0.0, 1.0) * h as f32).round() as u32;
+    let x1 = ((c.x + c.width).clamp(0.0, 1.0) * w as f32).round() as u32;
+    let y1 = ((c.y + c.height).clamp(0.0, 1.0) * h as f32).round() as u32;
+
+    let x0 = x0.min(w.saturating_sub(1));
+    let y0 = y0.min(h.saturating_sub(1));
+    let cw = x1.saturating_sub(x0).max(1);
+    let ch = y1.saturating_sub(y0).max(1);
+
+    (x0, y0, cw, ch)
 }
 
 #[derive(Serialize)]
 struct ImageResult {
     width: u32,
     height: u32,
-    data: Vec<f32>, // Linear RGB Float data
+    #[serde(skip)]
+    data: Vec<f32>, // Linear RGB Float data, sent as binary after the JSON header
+    exif: ExifInfo,
+    orientation: u16,
+    // Part of the image the data covers (normalized, display orientation)
+    // when the preview was decoded for a crop only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roi: Option<CropRect>,
+    // Non-fatal: colours may be inaccurate for this camera
+    color_profile_missing: bool,
+    // EV already applied on top of the user's exposure
+    baseline_exposure: f32,
+    // Non-fatal: the as-shot white balance was missing and estimated
+    wb_estimated: bool,
+    // The file carries a vignetting profile (see ImageParams::profile_vignetting)
+    vignette_profile_found: bool,
+    // Starting point for the temperature / tint sliders
+    as_shot_wb: Option<(f32, f32)>,
+    // No CFA (e.g. Leica Monochrom): data is grey, with no colour to balance
+    monochrome_sensor: bool,
+    // Rendered data only: frame-relative passes were left out because only a
+    // crop is loaded (see roi_skips_passes)
+    passes_skipped: bool,
+}
+
+impl ImageResult {
+    // Result for `data` decoded from ctx, same size as ctx unless overridden.
+    // Carries ctx's ROI, so a crop preview stays marked as one.
+    fn from_context(ctx: &PreviewContext, data: Vec<f32>) -> Self {
+        ImageResult {
+            width: ctx.width,
+            height: ctx.height,
+            data,
+            exif: ctx.exif.clone(),
+            orientation: ctx.orientation,
+            roi: ctx.roi,
+            color_profile_missing: ctx.color_profile_missing,
+            baseline_exposure: ctx.baseline_exposure,
+            wb_estimated: ctx.wb_estimated,
+            vignette_profile_found: ctx.vignette_profile.is_some(),
+            as_shot_wb: ctx.as_shot_wb,
+            monochrome_sensor: ctx.monochrome_sensor,
+            passes_skipped: false,
+        }
+    }
+
+    fn into_response(self) -> Result<Response, String> {
+        binary_response(&self, &self.data)
+    }
+}
+
+// Binary IPC payload: [u32 LE header length][JSON header][pad to 4][f32 LE data].
+// Avoids serializing millions of floats as JSON.
+fn binary_response(header: &impl Serialize, data: &[f32]) -> Result<Response, String> {
+    let mut bytes = binary_header(header, data.len() * 4)?;
+    for v in data {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    Ok(Response::new(bytes))
+}
+
+// Length, JSON header and padding of a binary payload, with room for
+// `data_len` bytes of data
+fn binary_header(header: &impl Serialize, data_len: usize) -> Result<Vec<u8>, String> {
+    let header = serde_json::to_vec(header).map_err(|e| e.to_string())?;
+    let padding = (4 - (4 + header.len()) % 4) % 4;
+
+    let mut bytes = Vec::with_capacity(4 + header.len() + padding + data_len);
+    bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header);
+    bytes.resize(bytes.len() + padding, b' ');
+    Ok(bytes)
+}
+
+#[derive(Serialize)]
+struct Histogram {
+    r: Vec<u32>,
+    g: Vec<u32>,
+    b: Vec<u32>,
+}
+
+// Luminance distribution per column: `counts[level * columns + column]`
+// pixels of that column have that luma level (0 = black, row-major with
+// the top row holding the darkest level)
+#[derive(Serialize)]
+struct Waveform {
+    columns: u32,
+    levels: u32,
+    counts: Vec<u32>,
+}
+
+// Chroma accumulation on the Cb/Cr plane: `counts[y * size + x]`, with Cb
+// along x and Cr along y (Cr = +0.5 at y = 0), neutral at the centre
+#[derive(Serialize)]
+struct Vectorscope {
+    size: u32,
+    counts: Vec<u32>,
+}
+
+// Per-channel statistics of processed (display-encoded) RGB over a region
+#[derive(Serialize)]
+struct RegionStats {
+    pixels: u32,
+    mean: [f32; 3],
+    median: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+const WAVEFORM_COLUMNS: u32 = 256;
+const WAVEFORM_LEVELS: u32 = 256;
+const VECTORSCOPE_SIZE: u32 = 128;
+
+// Preview-sized clipping bitmaps, one bit per pixel in row-major order
+// (pixel i is bit i % 8 of byte i / 8)
+#[derive(Serialize)]
+struct ClippingMask {
+    width: u32,
+    height: u32,
+    shadows: Vec<u8>,    // some channel at or below 0
+    highlights: Vec<u8>, // some channel at or above 1
+}
+
+// Preview-sized focus peaking bitmap, same bit layout as ClippingMask
+#[derive(Serialize)]
+struct PeakingMask {
+    width: u32,
+    height: u32,
+    edges: Vec<u8>,
+}
+
+// Monotonic cubic (Fritsch-Carlson style) interpolation through sorted points
+// (see deserialize_curve).
+// Flat extrapolation outside the first/last point.
+fn eval_curve(points: &[(f32, f32)], x: f32) -> f32 {
+    let n = points.len();
+    if n < 2 {
+        return x;
+    }
+    if x <= points[0].0 {
+        return points[0].1;
+    }
+    if x >= points[n - 1].0 {
+        return points[n - 1].1;
+    }
+
+    // Segment containing x
+    let k = points.partition_point(|p| p.0 <= x).clamp(1, n - 1) - 1;
+
+    let secant = |i: usize| -> f32 {
+        let dx = points[i + 1].0 - points[i].0;
+        if dx <= 0.0 {
+            0.0
+        } else {
+            (points[i + 1].1 - points[i].1) / dx
+        }
+    };
+    // Tangent at point i: harmonic mean of neighbouring secants, 0 at extrema
+    let tangent = |i: usize| -> f32 {
+        if i == 0 {
+            return secant(0);
+        }
+        if i == n - 1 {
+            return secant(n - 2);
+        }
+        let d0 = secant(i - 1);
+        let d1 = secant(i);
+        if d0 * d1 <= 0.0 {
+            0.0
+        } else {
+            2.0 * d0 * d1 / (d0 + d1)
+        }
+    };
+
+    let (x0, y0) = points[k];
+    let (x1, y1) = points[k + 1];
+    let h = x1 - x0;
+    if h <= 0.0 {
+        return y1;
+    }
+    let t = (x - x0) / h;
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+    let h10 = t3 - 2.0 * t2 + t;
+    let h01 = -2.0 * t3 + 3.0 * t2;
+    let h11 = t3 - t2;
+
+    h00 * y0 + h10 * h * tangent(k) + h01 * y1 + h11 * h * tangent(k + 1)
 }
 
+// Preview / analysis path: always sRGB
 fn apply_processing(r: f32, g: f32, b: f32, params: &ImageParams) -> (f32, f32, f32) {
+    apply_processing_in(r, g, b, params, OutputSpace::Srgb)
+}
+
+// A linear preview pixel as the viewer shows it: apply_processing, then the
+// 3D LUT. Shared by the region renders and the analysis commands so the
+// scopes measure what is displayed and exported.
+fn grade_pixel(px: &[f32], params: &ImageParams, lut: Option<&lut::Lut3d>) -> [f32; 3] {
+    let (r, g, b) = apply_processing(px[0], px[1], px[2], params);
+    lut.map_or([r, g, b], |lut| lut.apply([r, g, b]))
+}
+
+fn apply_processing_in(
+    r: f32,
+    g: f32,
+    b: f32,
+    params: &ImageParams,
+    space: OutputSpace,
+) -> (f32, f32, f32) {
+    let mut rgb = apply_layers(r, g, b, params);
+
+    // 8b. Tone mapping (highlight rolloff). Before gamut compression, which
+    // would otherwise turn everything with luminance above 1 into white.
+    if params.tone_mapping != ToneMapping::None {
+        rgb.iter_mut()
+            .for_each(|v| *v = params.tone_mapping.apply(*v));
+    }
+
+    // 8c. Gamut compression (display-referred, so not part of apply_layers)
+    if params.gamut_compress {
+        compress_gamut(&mut rgb);
+    }
+
+    // 9. Gamma (encoding of the output space)
+    if space == OutputSpace::Srgb && params.transfer_curve == TransferCurve::Srgb {
+        rgb.iter_mut().for_each(|v| *v = srgb_encode(v.max(0.0)));
+    } else {
+        let gamma = 1.0 / space.gamma();
+        rgb.iter_mut().for_each(|v| *v = v.max(0.0).powf(gamma));
+    }
+
+    (rgb[0], rgb[1], rgb[2])
+}
+
+// Steps 1-8 for params and then each of its layers; linear output
+fn apply_layers(r: f32, g: f32, b: f32, params: &ImageParams) -> [f32; 3] {
+    let mut rgb = apply_adjustments(r, g, b, params);
+    for layer in &params.layers {
+        let opacity = match layer.mask {
+            MaskSpec::Global => layer.opacity.clamp(0.0, 1.0),
+        };
+        if opacity > 0.0 {
+            let adjusted = apply_adjustments(rgb[0], rgb[1], rgb[2], &layer.params);
+            for (v, a) in rgb.iter_mut().zip(adjusted) {
+                *v += (a - *v) * opacity;
+            }
+        }
+    }
+    rgb
+}
+
+// Steps 1-8 of apply_processing: every adjustment, output still linear and
+// not clipped above 1
+fn apply_adjustments(r: f32, g: f32, b: f32, params: &ImageParams) -> [f32; 3] {
     let mut rgb = [r, g, b];
 
-    // 1. White Balance (Temp/Tint)
-    let ratio = (params.temperature - 5500.0) / 5500.0;
-    let wb_r = 1.0 + ratio.max(0.0);
-    let wb_b = 1.0 - ratio.min(0.0);
-    let wb_g = 1.0 + params.tint / 100.0;
+    // 0. Film negative inversion. Dividing by the base removes the mask and
+    // balances the channels (clear film becomes black), then the linear
+    // transmission is inverted.
+    if params.film_negative {
+        for (v, base) in rgb.iter_mut().zip(params.film_base) {
+            *v = (1.0 - *v / base.max(1e-6)).max(0.0);
+        }
+    }
+
+    // 1. White Balance (Temp/Tint), only in Custom mode.
+    // Temperature follows the blackbody locus (see wb.rs); tint is a green
+    // gain of 1 + tint / 100 on top.
+    if params.wb_mode == WbMode::Custom {
+        let [wb_r, _, wb_b] = wb::temperature_gains(params.temperature);
+        let wb_g = 1.0 + params.tint / 100.0;
 
-    rgb[0] *= wb_r;
-    rgb[1] *= wb_g;
-    rgb[2] *= wb_b;
+        rgb[0] *= wb_r;
+        rgb[1] *= wb_g;
+        rgb[2] *= wb_b;
+    }
+
+    // 1b. Channel gains (colour grading / residual tint)
+    rgb[0] *= params.red_gain;
+    rgb[1] *= params.green_gain;
+    rgb[2] *= params.blue_gain;
 
     // 2. Exposure
     if params.exposure != 0.0 {
@@ -67,12 +973,27 @@ fn apply_processing(r: f32, g: f32, b: f32, params: &ImageParams) -> (f32, f32,
         rgb[2] = (rgb[2] - 0.5) * c + 0.5;
     }
 
+    // 3b. Tone Curve (on luminance, evaluated in gamma space)
+    if params.curve.len() >= 2 {
+        let l = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+        if l > 0.0 {
+            let encoded = l.powf(1.0 / 2.2);
+            let mapped = eval_curve(&params.curve, encoded).max(0.0).powf(2.2);
+            let scale = mapped / l;
+            rgb[0] *= scale;
+            rgb[1] *= scale;
+            rgb[2] *= scale;
+        }
+    }
+
     // 4. Luma for Tone Mapping
     let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
 
     // 5. Highlights / Shadows
-    let shadow_mask = 1.0 - (luma / 0.6).clamp(0.0, 1.0);
-    let high_mask = ((luma - 0.4) / 0.6).clamp(0.0, 1.0);
+    let shadow_range = params.shadow_range.clamp(0.05, 1.0);
+    let highlight_range = params.highlight_range.clamp(0.05, 1.0);
+    let shadow_mask = 1.0 - (luma / shadow_range).clamp(0.0, 1.0);
+    let high_mask = ((luma - (1.0 - highlight_range)) / highlight_range).clamp(0.0, 1.0);
 
     if params.shadows != 0.0 {
         let lift = 2.0_f32.powf(params.shadows) - 1.0;
@@ -90,7 +1011,65 @@ fn apply_processing(r: f32, g: f32, b: f32, params: &ImageParams) -> (f32, f32,
         rgb[2] += rgb[2] * fact;
     }
 
-    // 6. Levels (Whites / Blacks)
+    // 6. Saturation
+    // Runs on the tone-mapped linear values, before levels can push channels
+    // negative. Negative inputs (e.g. from strong contrast) are clipped first
+    // so they can't flip hues.
+    rgb[0] = rgb[0].max(0.0);
+    rgb[1] = rgb[1].max(0.0);
+    rgb[2] = rgb[2].max(0.0);
+
+    let use_oklab = params.adjustment_space == AdjustmentSpace::Oklab;
+    if params.saturation != 0.0 && use_oklab {
+        oklab::adjust(&mut rgb, 1.0 + params.saturation, 0.0);
+    } else if params.saturation != 0.0 {
+        let l = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+        let sat_mult = 1.0 + params.saturation;
+        rgb[0] = l + (rgb[0] - l) * sat_mult;
+        rgb[1] = l + (rgb[1] - l) * sat_mult;
+        rgb[2] = l + (rgb[2] - l) * sat_mult;
+    }
+
+    // 6b. Vibrance: boost muted colors more than already-saturated ones
+    if params.vibrance != 0.0 {
+        let max = rgb[0].max(rgb[1]).max(rgb[2]);
+        let min = rgb[0].min(rgb[1]).min(rgb[2]);
+        let current_sat = if max > 0.0 {
+            ((max - min) / max).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let vib_mult = 1.0 + params.vibrance * (1.0 - current_sat);
+        if use_oklab {
+            oklab::adjust(&mut rgb, vib_mult, 0.0);
+        } else {
+            let l = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+            rgb[0] = l + (rgb[0] - l) * vib_mult;
+            rgb[1] = l + (rgb[1] - l) * vib_mult;
+            rgb[2] = l + (rgb[2] - l) * vib_mult;
+        }
+    }
+
+    // 6c. HSL (per colour band)
+    if !params.hsl.is_identity() && use_oklab {
+        hsl::apply_hsl_oklab(&mut rgb, &params.hsl);
+    } else if !params.hsl.is_identity() {
+        hsl::apply_hsl(&mut rgb, &params.hsl);
+    }
+
+    // 6d. Black & white channel mixer
+    if params.monochrome {
+        let mut weights = [params.bw_red, params.bw_green, params.bw_blue];
+        let mut total: f32 = weights.iter().sum();
+        if total.abs() < 1e-6 {
+            weights = [0.2126, 0.7152, 0.0722];
+            total = 1.0;
+        }
+        let grey = (weights[0] * rgb[0] + weights[1] * rgb[1] + weights[2] * rgb[2]) / total;
+        rgb = [grey.max(0.0); 3];
+    }
+
+    // 7. Levels (Whites / Blacks)
     let black_point = params.blacks * 0.2;
     let mut white_point = 1.0 + params.whites * 0.2;
     if white_point - black_point < 0.001 {
@@ -102,68 +1081,699 @@ fn apply_processing(r: f32, g: f32, b: f32, params: &ImageParams) -> (f32, f32,
     rgb[1] = (rgb[1] - black_point) / range;
     rgb[2] = (rgb[2] - black_point) / range;
 
-    // 7. Saturation
-    if params.saturation != 0.0 {
-        let l = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
-        let sat_mult = 1.0 + params.saturation;
-        rgb[0] = l + (rgb[0] - l) * sat_mult;
-        rgb[1] = l + (rgb[1] - l) * sat_mult;
-        rgb[2] = l + (rgb[2] - l) * sat_mult;
-    }
+    // Below the black point is black
+    rgb[0] = rgb[0].max(0.0);
+    rgb[1] = rgb[1].max(0.0);
+    rgb[2] = rgb[2].max(0.0);
 
-    // 8. Gamma (to sRGB)
-    let gamma = 1.0 / 2.2;
-    rgb[0] = rgb[0].max(0.0).powf(gamma);
-    rgb[1] = rgb[1].max(0.0).powf(gamma);
-    rgb[2] = rgb[2].max(0.0).powf(gamma);
+    // 8. Split Toning
+    if params.shadow_sat != 0.0 || params.highlight_sat != 0.0 {
+        let l = (0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2])
+            .clamp(0.0, 1.0)
+            .powf(1.0 / 2.2);
+        // Balance moves the shadow/highlight crossover away from mid-grey
+        let pivot = 0.5 - params.balance.clamp(-1.0, 1.0) * 0.5;
+        let t = (l - pivot + 0.5).clamp(0.0, 1.0);
+        let high_w = t * t * (3.0 - 2.0 * t);
+        let shadow_w = 1.0 - high_w;
 
-    (rgb[0], rgb[1], rgb[2])
+        for (hue, sat, weight) in [
+            (params.shadow_hue, params.shadow_sat, shadow_w),
+            (params.highlight_hue, params.highlight_sat, high_w),
+        ] {
+            let amount = sat.clamp(0.0, 1.0) * weight;
+            if amount <= 0.0 {
+                continue;
+            }
+            // Luminance-neutral tint so toning doesn't change brightness
+            let tint = hsl::hue_to_rgb(hue);
+            let tint_l = 0.2126 * tint[0] + 0.7152 * tint[1] + 0.0722 * tint[2];
+            for (v, t) in rgb.iter_mut().zip(tint) {
+                let toned = *v * t / tint_l;
+                *v += (toned - *v) * amount;
+            }
+        }
+    }
+
+    rgb
 }
 
-fn process_libraw(path: &str, target_width: Option<usize>) -> Result<PreviewContext, String> {
-    unsafe {
-        let raw_data = libraw_sys::libraw_init(0);
-        if raw_data.is_null() {
-            return Err("Failed to init libraw".into());
-        }
+// Scales chroma down just enough that every channel fits in 0..1, keeping the
+// Rec.709 luminance and hue. Luminance above 1 can only become white.
+fn compress_gamut(rgb: &mut [f32; 3]) {
+    let l = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+    if l >= 1.0 {
+        *rgb = [1.0; 3];
+        return;
+    }
+    if l <= 0.0 {
+        *rgb = [0.0; 3];
+        return;
+    }
 
-        let c_path = CString::new(path).map_err(|_| "Invalid path")?;
-        if libraw_sys::libraw_open_file(raw_data, c_path.as_ptr()) != 0 {
-            libraw_sys::libraw_close(raw_data);
-            return Err("Failed to open file".into());
+    let mut t: f32 = 1.0;
+    for &c in rgb.iter() {
+        if c > 1.0 {
+            t = t.min((1.0 - l) / (c - l));
+        } else if c < 0.0 {
+            t = t.min(l / (l - c));
         }
-
-        if libraw_sys::libraw_unpack(raw_data) != 0 {
-            libraw_sys::libraw_close(raw_data);
-            return Err("Failed to unpack".into());
+    }
+    if t < 1.0 {
+        for c in rgb.iter_mut() {
+            *c = l + (*c - l) * t;
         }
+    }
+}
 
-        // Configure Params (accessing raw_data->params)
-        // Note: libraw_sys usage might require dereferencing raw pointers carefully
-        // output_bps = 16
-        // output_color = 1 (sRGB)
-        // no_auto_bright = 1
-        // use_camera_wb = 1
-        // gamm = [1.0, 1.0]
+// Colour labels ("CMYG", "RGBE", ...) of a four-colour filter array that
+// isn't plain RGB with two greens, None otherwise
+unsafe fn four_color_cfa(raw_data: *const libraw_sys::libraw_data_t) -> Option<String> {
+    let idata = &(*raw_data).idata;
+    if idata.colors != 4 {
+        return None;
+    }
+    c_str_field(&idata.cdesc).filter(|cdesc| cdesc != "RGBG")
+}
 
-        (*raw_data).params.output_bps = 16;
-        (*raw_data).params.user_qual = 3; // AHD interpolation
-        (*raw_data).params.output_color = 1; // sRGB
-        (*raw_data).params.no_auto_bright = 1;
-        (*raw_data).params.use_camera_wb = 1;
-        (*raw_data).params.gamm[0] = 1.0;
-        (*raw_data).params.gamm[1] = 1.0;
+// Whether libraw knows a camera-to-XYZ matrix for the camera. Four-colour
+// sensors can't be converted to RGB correctly without one; other sensors
+// fall back to an identity conversion.
+unsafe fn has_color_matrix(raw_data: *const libraw_sys::libraw_data_t) -> bool {
+    (*raw_data)
+        .color
+        .cam_xyz
+        .iter()
+        .flatten()
+        .any(|&v| v != 0.0)
+}
 
-        if libraw_sys::libraw_dcraw_process(raw_data) != 0 {
-            libraw_sys::libraw_close(raw_data);
-            return Err("Failed to process".into());
-        }
+// R, G, B multipliers must all be positive to be used as gains
+fn valid_wb_coeffs(coeffs: &[f32; 4]) -> bool {
+    coeffs[..3].iter().all(|&c| c.is_finite() && c > 0.0)
+}
 
-        let processed = libraw_sys::libraw_dcraw_make_mem_image(raw_data, ptr::null_mut());
+// Whether an as-shot decode has to fall back to daylight: files without
+// usable as-shot coefficients (missing, all zero) would get zero gains.
+// Monochrome sensors have nothing to balance.
+fn wb_estimated(wb_mode: WbMode, monochrome_sensor: bool, cam_mul: &[f32; 4]) -> bool {
+    wb_mode == WbMode::AsShot && !monochrome_sensor && !valid_wb_coeffs(cam_mul)
+}
+
+// Camera WB as (temperature, tint) in the Custom model, which starts from the
+// daylight multipliers: the as-shot gains relative to daylight are the ones
+// temperature / tint have to reproduce. Approximate, since the gains are
+// applied to camera RGB rather than output RGB.
+fn as_shot_temperature(cam_mul: &[f32; 4], pre_mul: &[f32; 4]) -> Option<(f32, f32)> {
+    if !valid_wb_coeffs(cam_mul) || !valid_wb_coeffs(pre_mul) {
+        return None;
+    }
+    let relative = |c: usize| (cam_mul[c] / pre_mul[c]) / (cam_mul[1] / pre_mul[1]);
+    Some(wb::temperature_tint_for_gains([
+        relative(0),
+        1.0,
+        relative(2),
+    ]))
+}
+
+// Per-channel white levels: some cameras saturate each CFA colour at a
+// different raw value. Normalizing all channels by the single global maximum
+// leaves the earliest-clipping channel short of 1.0 and tints bright areas,
+// so everything clips at the lowest channel white level (libraw's user_sat).
+// libraw then scales by white - black, so a white level below the full code
+// range of the bit depth still maps to 1.0. None keeps libraw's maximum.
+fn saturation_level(linear_max: [i64; 4], black: i64, maximum: i64) -> Option<i64> {
+    let white = linear_max.into_iter().filter(|&v| v > 0).min()?;
+    (white > black && white < maximum).then_some(white)
+}
+
+// Green equilibration for Bayer sensors: the greens on the red rows and on
+// the blue rows can differ slightly in sensitivity, which demosaicing turns
+// into a fine maze pattern. Scales the odd-row greens' signal (above black)
+// to the even-row mean. `green` marks the green sites of the 2x2 pattern at
+// the window origin, and `window` is (x, y, w, h) within the buffer.
+fn balance_greens(
+    cfa: &mut [u16],
+    stride: usize,
+    window: (usize, usize, usize, usize),
+    green: [[bool; 2]; 2],
+    black: f32,
+) {
+    let (x0, y0, w, h) = window;
+    let sites = |parity: usize| {
+        (0..h).filter(move |y| y % 2 == parity).flat_map(move |y| {
+            (0..w)
+                .filter(move |x| green[y % 2][x % 2])
+                .map(move |x| (y0 + y) * stride + x0 + x)
+        })
+    };
+    let mean = |parity: usize| {
+        let (sum, count) = sites(parity).fold((0.0_f64, 0_u64), |(sum, count), i| {
+            (sum + (cfa[i] as f32 - black).max(0.0) as f64, count + 1)
+        });
+        (count > 0).then(|| sum / count as f64)
+    };
+    let (Some(even), Some(odd)) = (mean(0), mean(1)) else {
+        return;
+    };
+    if odd <= 0.0 {
+        return;
+    }
+    // A sensitivity mismatch is a few percent; more than that is the scene
+    // (or not a two-green layout), so it's left alone
+    let gain = (even / odd) as f32;
+    if !(0.9..=1.1).contains(&gain) {
+        return;
+    }
+    for i in sites(1).collect::<Vec<_>>() {
+        let v = cfa[i] as f32;
+        if v > black {
+            cfa[i] = (black + (v - black) * gain).round().min(65535.0) as u16;
+        }
+    }
+}
+
+// DNG BaselineExposure in EV
+unsafe fn read_baseline_exposure(raw_data: *const libraw_sys::libraw_data_t) -> f32 {
+    baseline_exposure_ev((*raw_data).color.dng_levels.baseline_exposure)
+}
+
+// libraw leaves a large negative sentinel when the file has no
+// BaselineExposure, which counts as 0, as does anything implausible
+fn baseline_exposure_ev(ev: f32) -> f32 {
+    if ev.is_finite() && ev.abs() <= 10.0 {
+        ev
+    } else {
+        0.0
+    }
+}
+
+// Reads a fixed-size C string field from the libraw structs
+fn c_str_field(buf: &[c_char]) -> Option<String> {
+    let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len()) };
+    let s = CStr::from_bytes_until_nul(bytes).ok()?.to_string_lossy();
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+// Positive values only; libraw leaves missing tags at 0
+fn positive(v: f32) -> Option<f32> {
+    if v > 0.0 {
+        Some(v)
+    } else {
+        None
+    }
+}
+
+unsafe fn read_exif(raw_data: *const libraw_sys::libraw_data_t) -> ExifInfo {
+    let idata = &(*raw_data).idata;
+    let other = &(*raw_data).other;
+    let timestamp = other.timestamp as i64;
+
+    ExifInfo {
+        make: c_str_field(&idata.make),
+        model: c_str_field(&idata.model),
+        lens: c_str_field(&(*raw_data).lens.Lens),
+        iso: positive(other.iso_speed),
+        shutter: positive(other.shutter),
+        aperture: positive(other.aperture),
+        focal_length: positive(other.focal_len),
+        timestamp: if timestamp > 0 { Some(timestamp) } else { None },
+    }
+}
+
+// libraw's sizes.flip -> clockwise rotation in degrees
+fn flip_to_degrees(flip: i32) -> u16 {
+    match flip {
+        3 => 180,
+        5 => 270,
+        6 => 90,
+        _ => 0,
+    }
+}
+
+fn normalize_rotation(degrees: u16) -> Result<u16, String> {
+    let d = degrees % 360;
+    if d % 90 != 0 {
+        return Err(format!(
+            "Unsupported rotation: {} (use 0, 90, 180 or 270)",
+            degrees
+        ));
+    }
+    Ok(d)
+}
+
+// Source pixel for (x, y) of a w x h image rotated clockwise by `degrees`
+fn rotated_to_source(x: usize, y: usize, w: usize, h: usize, degrees: u16) -> (usize, usize) {
+    match degrees {
+        90 => (y, h - 1 - x),
+        180 => (w - 1 - x, h - 1 - y),
+        270 => (w - 1 - y, x),
+        _ => (x, y),
+    }
+}
+
+// A width x height RGBA buffer seen rotated clockwise by `degrees`, read in
+// place instead of copied by rotate_context
+struct Rotated<'a> {
+    data: &'a [f32],
+    width: usize,
+    height: usize,
+    degrees: u16,
+}
+
+impl Rotated<'_> {
+    // Size in the rotated orientation
+    fn dimensions(&self) -> (u32, u32) {
+        let (w, h) = (self.width as u32, self.height as u32);
+        if self.degrees % 180 == 0 {
+            (w, h)
+        } else {
+            (h, w)
+        }
+    }
+
+    // RGBA of (x, y) in the rotated orientation
+    fn pixel(&self, x: u32, y: u32) -> &[f32] {
+        let (sx, sy) = rotated_to_source(
+            x as usize,
+            y as usize,
+            self.width,
+            self.height,
+            self.degrees,
+        );
+        let i = (sy * self.width + sx) * 4;
+        &self.data[i..i + 4]
+    }
+}
+
+// Rotates an RGBA context clockwise by a multiple of 90 degrees
+fn rotate_context(ctx: &PreviewContext, degrees: u16) -> PreviewContext {
+    if degrees == 0 {
+        return ctx.clone();
+    }
+
+    let w = ctx.width as usize;
+    let h = ctx.height as usize;
+    let (out_w, out_h) = if degrees == 180 { (w, h) } else { (h, w) };
+    let mut data = vec![0.0; ctx.data.len()];
+
+    for dy in 0..out_h {
+        for dx in 0..out_w {
+            let (sx, sy) = rotated_to_source(dx, dy, w, h, degrees);
+            let src = (sy * w + sx) * 4;
+            let dst = (dy * out_w + dx) * 4;
+            data[dst..dst + 4].copy_from_slice(&ctx.data[src..src + 4]);
+        }
+    }
+
+    PreviewContext {
+        width: out_w as u32,
+        height: out_h as u32,
+        data,
+        exif: ctx.exif.clone(),
+        orientation: ctx.orientation,
+        color_profile_missing: ctx.color_profile_missing,
+        baseline_exposure: ctx.baseline_exposure,
+        wb_estimated: ctx.wb_estimated,
+        daylight_wb: ctx.daylight_wb,
+        vignette_profile: ctx.vignette_profile.map(|v| v.rotated(degrees)),
+        as_shot_wb: ctx.as_shot_wb,
+        monochrome_sensor: ctx.monochrome_sensor,
+        roi: ctx.roi,
+    }
+}
+
+// LibRaw_errors codes that map to specific AppError kinds
+const LIBRAW_FILE_UNSUPPORTED: i32 = -2;
+const LIBRAW_IO_ERROR: i32 = -100009;
+
+// A failed libraw_open_file as an AppError
+fn open_error(ret: i32) -> AppError {
+    let reason = unsafe { CStr::from_ptr(libraw_sys::libraw_strerror(ret)) }
+        .to_string_lossy()
+        .into_owned();
+    match ret {
+        LIBRAW_FILE_UNSUPPORTED => AppError::UnsupportedFormat(reason),
+        LIBRAW_IO_ERROR => AppError::Io(reason),
+        _ => AppError::Decode(reason),
+    }
+}
+
+// libraw_const.h: LibRaw_processing_options
+const LIBRAW_PROCESSING_CONVERTFLOAT_TO_INT: u32 = 1 << 7;
+
+// Per-phase export timings in milliseconds. decode = open + unpack,
+// demosaic = libraw's processing, process = our pipeline, encode = file write.
+// Phases served from the full-res cache stay at 0.
+#[derive(Serialize, Default, Clone, Copy)]
+struct ExportStats {
+    decode_ms: f64,
+    demosaic_ms: f64,
+    process_ms: f64,
+    encode_ms: f64,
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+fn process_libraw(
+    path: &str,
+    target_dim: Option<usize>, // longest edge of the output, None = full size
+    full_quality: bool,
+    options: DecodeOptions,
+) -> Result<PreviewContext, AppError> {
+    process_libraw_timed(
+        path,
+        target_dim,
+        full_quality,
+        options,
+        None,
+        &mut ExportStats::default(),
+    )
+}
+
+// Clockwise rotation from sensor layout to display. An explicit rotation is
+// absolute and wins; otherwise the camera's orientation flag is followed
+// when auto_orient is on, and the sensor layout kept (0) when it's off.
+fn display_rotation(rotation: Option<u16>, auto_orient: bool, orientation: u16) -> u16 {
+    rotation.unwrap_or(if auto_orient { orientation } else { 0 })
+}
+
+// Region of interest for a decode: a display-space crop plus the rotation
+// settings the display uses
+struct Roi {
+    crop: CropRect,
+    rotation: Option<u16>,
+    auto_orient: bool,
+}
+
+// Shape of libraw's processed bitmap: interleaved channels, 8 or 16 bits
+struct RawLayout {
+    width: usize,
+    height: usize,
+    channels: usize,
+    bits: usize,
+}
+
+// Converts a processed bitmap to the RGBA f32 preview buffer, keeping one
+// output pixel per `step` source pixels in each direction. Each output pixel
+// is the mean of the block x block source pixels at its corner, scaled by
+// gain. Single-channel (monochrome linear) data repeats its one channel.
+fn pack_rgba(
+    raw_bytes: &[u8],
+    layout: &RawLayout,
+    step: usize,
+    block: usize,
+    gain: f32,
+) -> Vec<f32> {
+    let RawLayout {
+        width: w,
+        height: h,
+        channels,
+        bits,
+    } = *layout;
+    let out_w = w / step;
+    let out_h = h / step;
+    let mut out_data = Vec::with_capacity(out_w * out_h * 4);
+
+    let read_val = |x: usize, y: usize, c: usize| -> f32 {
+        let pixel_idx = y * w + x;
+        let c = c.min(channels.saturating_sub(1));
+
+        if bits == 16 {
+            let byte_idx = pixel_idx * channels * 2 + c * 2;
+            if byte_idx + 1 >= raw_bytes.len() {
+                return 0.0;
+            }
+            let val = u16::from_le_bytes([raw_bytes[byte_idx], raw_bytes[byte_idx + 1]]);
+            val as f32 / 65535.0
+        } else {
+            // 8-bit case
+            let byte_idx = pixel_idx * channels + c;
+            if byte_idx >= raw_bytes.len() {
+                return 0.0;
+            }
+            let val = raw_bytes[byte_idx];
+            val as f32 / 255.0
+        }
+    };
+
+    let norm = gain / (block * block) as f32;
+    for y in 0..out_h {
+        let src_y = y * step;
+        for x in 0..out_w {
+            let src_x = x * step;
+
+            let mut r = 0.0;
+            let mut g = 0.0;
+            let mut b = 0.0;
+            for by in 0..block {
+                for bx in 0..block {
+                    r += read_val(src_x + bx, src_y + by, 0);
+                    g += read_val(src_x + bx, src_y + by, 1);
+                    b += read_val(src_x + bx, src_y + by, 2);
+                }
+            }
+
+            out_data.push(r * norm);
+            out_data.push(g * norm);
+            out_data.push(b * norm);
+            out_data.push(1.0);
+        }
+    }
+    out_data
+}
+
+// Corrupt files can report dimensions the decoded buffer doesn't cover; fail
+// instead of decoding a mostly black image
+fn check_buffer(
+    w: usize,
+    h: usize,
+    channels: usize,
+    bits: usize,
+    len: usize,
+) -> Result<(), AppError> {
+    let expected = w
+        .checked_mul(h)
+        .and_then(|n| n.checked_mul(channels))
+        .and_then(|n| n.checked_mul(bits.div_ceil(8)));
+    if w == 0 || h == 0 || channels == 0 || !expected.is_some_and(|n| n <= len) {
+        return Err(AppError::Decode(format!(
+            "Corrupt image data: {}x{}x{} at {} bits needs more than the {} bytes decoded",
+            w, h, channels, bits, len
+        )));
+    }
+    Ok(())
+}
+
+fn process_libraw_timed(
+    path: &str,
+    target_dim: Option<usize>,
+    full_quality: bool,
+    options: DecodeOptions,
+    roi: Option<&Roi>,
+    stats: &mut ExportStats,
+) -> Result<PreviewContext, AppError> {
+    let decode_start = Instant::now();
+    // Before libraw_init, so a bad path can't leak the handle
+    let c_path = CString::new(path).map_err(|_| AppError::InvalidInput("Invalid path".into()))?;
+    let _libraw = LIBRAW.lock().unwrap();
+    unsafe {
+        let raw_data = libraw_sys::libraw_init(0);
+        if raw_data.is_null() {
+            return Err(AppError::Decode("Failed to init libraw".into()));
+        }
+
+        let ret = libraw_sys::libraw_open_file(raw_data, c_path.as_ptr());
+        if ret != 0 {
+            libraw_sys::libraw_close(raw_data);
+            return Err(open_error(ret));
+        }
+
+        // Floating-point raws (some DNGs) are rescaled to integers during
+        // unpack so the rest of the pipeline (black/white levels, WB, colour
+        // matrix) treats them like any other file
+        (*raw_data).params.raw_processing_options |= LIBRAW_PROCESSING_CONVERTFLOAT_TO_INT;
+
+        let ret = libraw_sys::libraw_unpack(raw_data);
+        if ret != 0 {
+            let reason = CStr::from_ptr(libraw_sys::libraw_strerror(ret)).to_string_lossy();
+            libraw_sys::libraw_close(raw_data);
+            return Err(AppError::Decode(format!("Failed to unpack: {}", reason)));
+        }
+        stats.decode_ms = elapsed_ms(decode_start);
+
+        // Configure Params (accessing raw_data->params)
+        // Note: libraw_sys usage might require dereferencing raw pointers carefully
+        // output_bps = 16
+        // output_color = 1 (sRGB)
+        // no_auto_bright = 1
+        // use_camera_wb = 1
+        // gamm = [1.0, 1.0]
+
+        // libraw converts CMYG / RGBE data through the camera's colour matrix;
+        // without one the colours would be wrong, so refuse instead
+        if let Some(cdesc) = four_color_cfa(raw_data) {
+            if !has_color_matrix(raw_data) {
+                libraw_sys::libraw_close(raw_data);
+                return Err(AppError::UnsupportedFormat(format!(
+                    "No colour matrix for this {} sensor",
+                    cdesc
+                )));
+            }
+        }
+
+        let exif = read_exif(raw_data);
+        let orientation = flip_to_degrees((*raw_data).sizes.flip);
+        // Monochrome sensors decode to a single channel, which read_val
+        // repeats as grey. There's no colour matrix or WB to miss.
+        let monochrome_sensor = (*raw_data).idata.colors == 1;
+        let color_profile_missing = !monochrome_sensor && !has_color_matrix(raw_data);
+        // Applied at decode so previews, regions and exports all include it;
+        // the user's exposure works as an offset from there
+        let baseline_exposure = read_baseline_exposure(raw_data);
+        let baseline_gain = 2.0_f32.powf(baseline_exposure);
+        // The profile is relative to the full frame, so ROI decodes skip it
+        let vignette_profile = if roi.is_none() {
+            dng::vignette_profile(path)
+        } else {
+            None
+        };
+
+        // Only the ROI is demosaiced and converted (libraw's cropbox, in
+        // sensor pixels). The output is then just the cropped area.
+        let sensor_w = (*raw_data).sizes.width as u32;
+        let sensor_h = (*raw_data).sizes.height as u32;
+        let mut region_long = sensor_w.max(sensor_h) as usize;
+        if let Some(roi) = roi {
+            let degrees = match normalize_rotation(display_rotation(
+                roi.rotation,
+                roi.auto_orient,
+                orientation,
+            )) {
+                Ok(d) => d,
+                Err(e) => {
+                    libraw_sys::libraw_close(raw_data);
+                    return Err(AppError::InvalidInput(e));
+                }
+            };
+            let source = display_rect_to_source(&roi.crop, degrees);
+            let (x, y, cw, ch) = crop_to_pixels(Some(&source), sensor_w, sensor_h);
+            (*raw_data).params.cropbox = [x, y, cw, ch];
+            region_long = cw.max(ch) as usize;
+        }
+
+        // filters == 9 marks a Fuji X-Trans 6x6 CFA
+        let is_xtrans = (*raw_data).idata.filters == 9;
+        let cfa_size = if is_xtrans { 6 } else { 2 };
+        // filters == 0: no mosaic (linear DNGs and other already-demosaiced
+        // data). libraw only scales, white balances and colour-converts these.
+        let is_linear = (*raw_data).idata.filters == 0;
+
+        let raw_image = (*raw_data).rawdata.raw_image;
+        if !is_xtrans && !is_linear && (*raw_data).idata.colors == 3 && !raw_image.is_null() {
+            let sizes = &(*raw_data).sizes;
+            let stride = sizes.raw_pitch as usize / 2;
+            let cfa = std::slice::from_raw_parts_mut(raw_image, stride * sizes.raw_height as usize);
+            // libraw_COLOR is in visible coordinates, the window's origin;
+            // the second green may be reported as colour 3
+            let mut green = [[false; 2]; 2];
+            for (row, sites) in green.iter_mut().enumerate() {
+                for (col, site) in sites.iter_mut().enumerate() {
+                    let c = libraw_sys::libraw_COLOR(raw_data, row as i32, col as i32);
+                    *site = c == 1 || c == 3;
+                }
+            }
+            let window = (
+                sizes.left_margin as usize,
+                sizes.top_margin as usize,
+                sizes.width as usize,
+                sizes.height as usize,
+            );
+            balance_greens(cfa, stride, window, green, (*raw_data).color.black as f32);
+        }
+
+        (*raw_data).params.output_bps = 16;
+        if is_linear {
+            // Nothing to interpolate and no 2x2 blocks to merge; the full-size
+            // RGB data is subsampled by `step` below
+        } else if full_quality {
+            // Demosaic every photosite with the chosen interpolation
+            // (for X-Trans, libraw uses Markesteijn whatever user_qual says)
+            match options.demosaic {
+                DemosaicQuality::Superpixel => (*raw_data).params.half_size = 1,
+                DemosaicQuality::Bilinear => (*raw_data).params.user_qual = 0,
+                DemosaicQuality::Vng => (*raw_data).params.user_qual = 1,
+                DemosaicQuality::Ahd => (*raw_data).params.user_qual = 3,
+                DemosaicQuality::AhdLowNoise => {
+                    (*raw_data).params.user_qual = 3;
+                    (*raw_data).params.med_passes = CHROMA_MEDIAN_PASSES;
+                }
+            }
+        } else if is_xtrans {
+            // X-Trans has no 2x2 superpixel; use the fast 1-pass interpolation
+            // and average whole CFA blocks below
+            (*raw_data).params.user_qual = 0;
+        } else if target_dim.is_some_and(|t| t > region_long / 2) {
+            // Requested preview is larger than a superpixel image can provide
+            (*raw_data).params.user_qual = 0; // bilinear, fast
+        } else {
+            // Superpixel: each 2x2 CFA block becomes one RGB pixel (half size)
+            (*raw_data).params.half_size = 1;
+        }
+        let color = &(*raw_data).color;
+        if let Some(white) = saturation_level(
+            color.linear_max.map(|v| v as i64),
+            color.black as i64,
+            color.maximum as i64,
+        ) {
+            (*raw_data).params.user_sat = white as i32;
+        }
+
+        // Highlight rebuild: clipped channels are reconstructed from the
+        // unclipped ones using colour ratios of neighbouring pixels (dcraw -H 5)
+        (*raw_data).params.highlight = 5;
+        (*raw_data).params.user_flip = 0; // keep sensor layout, rotation is applied by us
+        (*raw_data).params.output_color = options.space.libraw_code(); // 1 = sRGB
+        (*raw_data).params.no_auto_bright = 1;
+        // Custom WB starts from libraw's daylight multipliers.
+        // Both greens of an RGGB sensor share colour index 1 unless
+        // four_color_rgb is set (it isn't), so G1/G2 get the same multiplier
+        // (their sensitivities were matched by balance_greens above); in
+        // 4-colour mode libraw fills a missing cam_mul[3] from cam_mul[1].
+        let wb_estimated = wb_estimated(
+            options.wb_mode,
+            monochrome_sensor,
+            &(*raw_data).color.cam_mul,
+        );
+        (*raw_data).params.use_camera_wb =
+            (options.wb_mode == WbMode::AsShot && !wb_estimated && !monochrome_sensor) as i32;
+        let as_shot_wb = if monochrome_sensor {
+            None
+        } else {
+            as_shot_temperature(&(*raw_data).color.cam_mul, &(*raw_data).color.pre_mul)
+        };
+        (*raw_data).params.gamm[0] = 1.0;
+        (*raw_data).params.gamm[1] = 1.0;
+
+        let demosaic_start = Instant::now();
+        if libraw_sys::libraw_dcraw_process(raw_data) != 0 {
+            libraw_sys::libraw_close(raw_data);
+            return Err(AppError::Decode("Failed to process".into()));
+        }
+
+        let processed = libraw_sys::libraw_dcraw_make_mem_image(raw_data, ptr::null_mut());
         if processed.is_null() {
             libraw_sys::libraw_close(raw_data);
-            return Err("Failed to make mem image".into());
+            return Err(AppError::Decode("Failed to make mem image".into()));
         }
+        stats.demosaic_ms = elapsed_ms(demosaic_start);
 
         let w = (*processed).width as usize;
         let h = (*processed).height as usize;
@@ -181,13 +1791,21 @@ fn process_libraw(path: &str, target_width: Option<usize>) -> Result<PreviewCont
 
         // Data is in (*processed).data which is slice of bytes
         let data_size = (*processed).data_size as usize;
+        if let Err(e) = check_buffer(w, h, channels, bits, data_size) {
+            libraw_sys::libraw_dcraw_clear_mem(processed);
+            libraw_sys::libraw_close(raw_data);
+            return Err(e);
+        }
         let raw_bytes = std::slice::from_raw_parts((*processed).data.as_ptr(), data_size);
 
         // Determine Step
-        let step = if let Some(target) = target_width {
-            let s = (w as f32 / target as f32).ceil() as usize;
+        let step = if let Some(target) = target_dim {
+            let s = (w.max(h) as f32 / target.max(1) as f32).ceil() as usize;
             if s < 1 {
                 1
+            } else if is_xtrans && s < cfa_size {
+                // Average at least one full X-Trans period to hide the pattern
+                cfa_size
             } else {
                 s
             }
@@ -199,44 +1817,16 @@ fn process_libraw(path: &str, target_width: Option<usize>) -> Result<PreviewCont
         let out_h = h / step;
         println!("Output: {}x{} (step={})", out_w, out_h, step);
 
-        let mut out_data = Vec::with_capacity(out_w * out_h * 4);
-
-        let read_val = |x: usize, y: usize, c: usize| -> f32 {
-            let pixel_idx = y * w + x;
-
-            if bits == 16 {
-                let byte_idx = pixel_idx * channels * 2 + c * 2;
-                if byte_idx + 1 >= raw_bytes.len() {
-                    return 0.0;
-                }
-                let val = u16::from_le_bytes([raw_bytes[byte_idx], raw_bytes[byte_idx + 1]]);
-                val as f32 / 65535.0
-            } else {
-                // 8-bit case
-                let byte_idx = pixel_idx * channels + c;
-                if byte_idx >= raw_bytes.len() {
-                    return 0.0;
-                }
-                let val = raw_bytes[byte_idx];
-                val as f32 / 255.0
-            }
+        // Block size for averaging: the whole step for X-Trans previews,
+        // a single sample otherwise
+        let block = if is_xtrans { step } else { 1 };
+        let layout = RawLayout {
+            width: w,
+            height: h,
+            channels,
+            bits,
         };
-
-        for y in 0..out_h {
-            let src_y = y * step;
-            for x in 0..out_w {
-                let src_x = x * step;
-
-                let r = read_val(src_x, src_y, 0);
-                let g = read_val(src_x, src_y, 1);
-                let b = read_val(src_x, src_y, 2);
-
-                out_data.push(r);
-                out_data.push(g);
-                out_data.push(b);
-                out_data.push(1.0);
-            }
-        }
+        let out_data = pack_rgba(raw_bytes, &layout, step, block, baseline_gain);
 
         libraw_sys::libraw_dcraw_clear_mem(processed);
         libraw_sys::libraw_close(raw_data);
@@ -245,83 +1835,3491 @@ fn process_libraw(path: &str, target_width: Option<usize>) -> Result<PreviewCont
             width: out_w as u32,
             height: out_h as u32,
             data: out_data,
+            exif,
+            orientation,
+            color_profile_missing,
+            baseline_exposure,
+            wb_estimated,
+            daylight_wb: options.wb_mode == WbMode::Custom || wb_estimated,
+            vignette_profile,
+            as_shot_wb,
+            monochrome_sensor,
+            roi: None,
         })
     }
 }
 
-#[tauri::command]
-fn load_raw(state: State<AppState>, path: &str) -> Result<ImageResult, String> {
-    // Preview Target: 1024px
-    let preview = process_libraw(path, Some(1024))?;
+// Decodes the camera-embedded preview (usually a JPEG) without touching the
+// raw data. Pixels are linearized so they match the process_libraw output.
+fn decode_thumbnail(path: &str) -> Result<PreviewContext, AppError> {
+    let (thumb, exif, orientation) = extract_thumbnail(path)?;
+    let rgb = thumb.decode().map_err(AppError::Decode)?;
+    let mut data = Vec::with_capacity(rgb.width() as usize * rgb.height() as usize * 4);
+    for px in rgb.pixels() {
+        // sRGB-encoded 8-bit -> linear
+        data.push(srgb_decode(px[0] as f32 / 255.0));
+        data.push(srgb_decode(px[1] as f32 / 255.0));
+        data.push(srgb_decode(px[2] as f32 / 255.0));
+        data.push(1.0);
+    }
 
-    let result = ImageResult {
-        width: preview.width,
-        height: preview.height,
-        data: preview.data.clone(),
-    };
-    *state.preview_context.lock().unwrap() = Some(preview);
-    Ok(result)
+    Ok(PreviewContext {
+        width: rgb.width(),
+        height: rgb.height(),
+        data,
+        exif,
+        orientation,
+        color_profile_missing: false,
+        baseline_exposure: 0.0,
+        wb_estimated: false,
+        daylight_wb: false,
+        vignette_profile: None,
+        as_shot_wb: None,
+        monochrome_sensor: false,
+        roi: None,
+    })
 }
 
-#[tauri::command]
-fn export_image(path: &str, params: ImageParams, save_path: &str) -> Result<(), String> {
-    // Full Export: No target width (Full Res)
-    let processed = process_libraw(path, None)?;
+// Embedded preview copied out of libraw, so it can be decoded without holding
+// a libraw handle (and on other threads)
+enum EmbeddedThumb {
+    Jpeg(Vec<u8>),
+    Bitmap(u32, u32, Vec<u8>), // 8-bit RGB
+}
+
+impl EmbeddedThumb {
+    fn decode(self) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, String> {
+        match self {
+            EmbeddedThumb::Jpeg(bytes) => image::load_from_memory(&bytes)
+                .map(|img| img.to_rgb8())
+                .map_err(|e| e.to_string()),
+            EmbeddedThumb::Bitmap(w, h, bytes) => {
+                ImageBuffer::from_raw(w, h, bytes).ok_or_else(|| "Invalid bitmap thumbnail".into())
+            }
+        }
+    }
+}
+
+fn extract_thumbnail(path: &str) -> Result<(EmbeddedThumb, ExifInfo, u16), AppError> {
+    // Before libraw_init, so a bad path can't leak the handle
+    let c_path = CString::new(path).map_err(|_| AppError::InvalidInput("Invalid path".into()))?;
+    let _libraw = LIBRAW.lock().unwrap();
+    unsafe {
+        let raw_data = libraw_sys::libraw_init(0);
+        if raw_data.is_null() {
+            return Err(AppError::Decode("Failed to init libraw".into()));
+        }
+
+        let ret = libraw_sys::libraw_open_file(raw_data, c_path.as_ptr());
+        if ret != 0 {
+            libraw_sys::libraw_close(raw_data);
+            return Err(open_error(ret));
+        }
+
+        if libraw_sys::libraw_unpack_thumb(raw_data) != 0 {
+            libraw_sys::libraw_close(raw_data);
+            return Err(AppError::Decode("No embedded preview".into()));
+        }
+
+        let exif = read_exif(raw_data);
+        let orientation = flip_to_degrees((*raw_data).sizes.flip);
+
+        let mut err = 0;
+        let thumb = libraw_sys::libraw_dcraw_make_mem_thumb(raw_data, &mut err);
+        if thumb.is_null() {
+            libraw_sys::libraw_close(raw_data);
+            return Err(AppError::Decode("Failed to make mem thumb".into()));
+        }
 
-    let w = processed.width;
-    let h = processed.height;
+        let data_size = (*thumb).data_size as usize;
+        let bytes = std::slice::from_raw_parts((*thumb).data.as_ptr(), data_size).to_vec();
 
-    let mut imgbuf: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(w, h);
+        // type 1 = JPEG stream, 2 = 8-bit RGB bitmap
+        let embedded = match (*thumb).type_ as i32 {
+            1 => Ok(EmbeddedThumb::Jpeg(bytes)),
+            2 => Ok(EmbeddedThumb::Bitmap(
+                (*thumb).width as u32,
+                (*thumb).height as u32,
+                bytes,
+            )),
+            _ => Err(AppError::UnsupportedFormat(
+                "Unsupported thumbnail format".into(),
+            )),
+        };
+
+        libraw_sys::libraw_dcraw_clear_mem(thumb);
+        libraw_sys::libraw_close(raw_data);
 
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        let idx = (y * w + x) as usize * 4;
-        let r_lin = processed.data[idx];
-        let g_lin = processed.data[idx + 1];
-        let b_lin = processed.data[idx + 2];
+        Ok((embedded?, exif, orientation))
+    }
+}
 
-        let (r_out, g_out, b_out) = apply_processing(r_lin, g_lin, b_lin, &params);
+// One file of generate_thumbnails. Its pixels are width * height * 3 bytes
+// (8-bit sRGB, RGB interleaved, display orientation) at `offset` in the data
+// after the header; none when `error` is set.
+#[derive(Serialize)]
+struct ThumbResult {
+    path: String,
+    width: u32,
+    height: u32,
+    offset: usize,
+    error: Option<String>,
+}
 
-        let r8 = (r_out.clamp(0.0, 1.0) * 255.0) as u8;
-        let g8 = (g_out.clamp(0.0, 1.0) * 255.0) as u8;
-        let b8 = (b_out.clamp(0.0, 1.0) * 255.0) as u8;
+// Small 8-bit thumbnail, upright and fitting max_dim
+fn finish_thumbnail(
+    rgb: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    orientation: u16,
+    max_dim: u32,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (w, h) = rgb.dimensions();
+    let rgb = if w.max(h) > max_dim {
+        let scale = max_dim as f32 / w.max(h) as f32;
+        let new_w = ((w as f32 * scale).round() as u32).max(1);
+        let new_h = ((h as f32 * scale).round() as u32).max(1);
+        imageops::thumbnail(&rgb, new_w, new_h)
+    } else {
+        rgb
+    };
+    match orientation {
+        90 => imageops::rotate90(&rgb),
+        180 => imageops::rotate180(&rgb),
+        270 => imageops::rotate270(&rgb),
+        _ => rgb,
+    }
+}
 
-        *pixel = Rgb([r8, g8, b8]);
+// Coarse raw decode for files without an embedded preview, neutral edit
+fn raw_thumbnail(path: &str, max_dim: u32) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, String> {
+    let options = DecodeOptions {
+        space: OutputSpace::Srgb,
+        wb_mode: WbMode::AsShot,
+        demosaic: DemosaicQuality::default(),
+    };
+    let ctx = process_libraw(path, Some(max_dim as usize), false, options)?;
+    let params = ImageParams::default();
+    let mut rgb = ImageBuffer::new(ctx.width, ctx.height);
+    for (pixel, px) in rgb.pixels_mut().zip(ctx.data.chunks_exact(4)) {
+        let (r, g, b) = apply_processing(px[0], px[1], px[2], &params);
+        *pixel = Rgb([
+            quantize_u8(r, 0, 0, false),
+            quantize_u8(g, 0, 0, false),
+            quantize_u8(b, 0, 0, false),
+        ]);
     }
+    Ok(finish_thumbnail(rgb, ctx.orientation, max_dim))
+}
 
-    imgbuf.save(save_path).map_err(|e| e.to_string())?;
-    Ok(())
+// Decodes and downscales embedded previews on `workers` threads. Each file
+// keeps a fixed worker and its result goes back to its own index, so the
+// output is the same whatever the thread count. None where there was no
+// preview or it didn't decode.
+fn decode_embedded(
+    embedded: Vec<Option<(EmbeddedThumb, u16)>>,
+    max_dim: u32,
+    workers: usize,
+) -> Vec<Option<Result<ImageBuffer<Rgb<u8>, Vec<u8>>, String>>> {
+    let workers = workers.max(1);
+    let mut jobs: Vec<Vec<(usize, EmbeddedThumb, u16)>> =
+        (0..workers).map(|_| Vec::new()).collect();
+    let count = embedded.len();
+    for (i, item) in embedded.into_iter().enumerate() {
+        if let Some((thumb, orientation)) = item {
+            jobs[i % workers].push((i, thumb, orientation));
+        }
+    }
+    let mut decoded: Vec<Option<Result<ImageBuffer<Rgb<u8>, Vec<u8>>, String>>> =
+        (0..count).map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|job| {
+                let indices: Vec<usize> = job.iter().map(|&(i, _, _)| i).collect();
+                let handle = scope.spawn(move || {
+                    job.into_iter()
+                        .filter_map(|(i, thumb, orientation)| {
+                            let rgb = thumb.decode().ok()?;
+                            Some((i, finish_thumbnail(rgb, orientation, max_dim)))
+                        })
+                        .collect::<Vec<_>>()
+                });
+                (indices, handle)
+            })
+            .collect();
+        for (indices, handle) in handles {
+            match handle.join() {
+                Ok(results) => {
+                    for (i, rgb) in results {
+                        decoded[i] = Some(Ok(rgb));
+                    }
+                }
+                // A panicking worker fails its own files, not the batch
+                Err(_) => {
+                    for i in indices {
+                        decoded[i] = Some(Err("Thumbnail decoding panicked".into()));
+                    }
+                }
+            }
+        }
+    });
+    decoded
 }
 
+// Contact-sheet thumbnails for a folder view, built for throughput: embedded
+// previews where possible, else a coarse raw decode. Runs on the blocking
+// pool; each libraw call takes LIBRAW for just that file, while decoding and
+// downscaling the embedded JPEGs runs in parallel. Failures are flagged per
+// file. Replies with the binary layout of load_raw: a
+// Vec<ThumbResult> header, then the pixels of all thumbnails as raw bytes.
 #[tauri::command]
-fn save_params(path: &str, params: ImageParams) -> Result<(), String> {
-    let json_val = serde_json::to_string_pretty(&params).map_err(|e| e.to_string())?;
-    let mut file = File::create(path).map_err(|e| e.to_string())?;
-    file.write_all(json_val.as_bytes())
-        .map_err(|e| e.to_string())?;
-    Ok(())
+async fn generate_thumbnails(paths: Vec<String>, max_dim: u32) -> Result<Response, String> {
+    let max_dim = max_dim.max(1);
+    tauri::async_runtime::spawn_blocking(move || {
+        let embedded: Vec<Option<(EmbeddedThumb, u16)>> = paths
+            .iter()
+            .map(|path| {
+                extract_thumbnail(path)
+                    .ok()
+                    .map(|(thumb, _, orientation)| (thumb, orientation))
+            })
+            .collect();
+
+        // Embedded previews are decoded and downscaled across worker threads
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let decoded = decode_embedded(embedded, max_dim, workers);
+
+        // Files without a usable embedded preview fall back to the raw data
+        let thumbs: Vec<Result<ImageBuffer<Rgb<u8>, Vec<u8>>, String>> = paths
+            .iter()
+            .zip(decoded)
+            .map(|(path, thumb)| match thumb {
+                Some(result) => result,
+                None => raw_thumbnail(path, max_dim),
+            })
+            .collect();
+
+        let mut offset = 0;
+        let entries: Vec<ThumbResult> = paths
+            .into_iter()
+            .zip(&thumbs)
+            .map(|(path, thumb)| match thumb {
+                Ok(rgb) => {
+                    let entry = ThumbResult {
+                        path,
+                        width: rgb.width(),
+                        height: rgb.height(),
+                        offset,
+                        error: None,
+                    };
+                    offset += rgb.as_raw().len();
+                    entry
+                }
+                Err(e) => ThumbResult {
+                    path,
+                    width: 0,
+                    height: 0,
+                    offset,
+                    error: Some(e.clone()),
+                },
+            })
+            .collect();
+
+        let mut bytes = binary_header(&entries, offset)?;
+        for rgb in thumbs.iter().flatten() {
+            bytes.extend_from_slice(rgb.as_raw());
+        }
+        Ok(Response::new(bytes))
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
+// async with the decode on the blocking pool, so the UI stays responsive.
+// A newer load cancels this one: it is skipped if still queued and its
+// result is dropped otherwise, so the latest request always wins.
 #[tauri::command]
-fn load_params(path: &str) -> Result<ImageParams, String> {
-    let file = File::open(path).map_err(|e| e.to_string())?;
-    let params: ImageParams = serde_json::from_reader(file).map_err(|e| e.to_string())?;
-    Ok(params)
+async fn load_raw(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: &str,
+    rotation: Option<u16>,
+    max_preview_dim: Option<u32>,
+    wb_mode: Option<WbMode>,
+    crop: Option<CropRect>,
+    auto_orient: Option<bool>,
+) -> Result<Response, AppError> {
+    // Preview Target: 1024px unless the UI asks for more (e.g. high-DPI displays)
+    let target = max_preview_dim.unwrap_or(1024) as usize;
+    let options = DecodeOptions {
+        space: OutputSpace::Srgb,
+        wb_mode: wb_mode.unwrap_or_default(),
+        demosaic: DemosaicQuality::default(),
+    };
+    // With a crop, only that part is decoded (at the full preview size). The
+    // frame-relative passes and the analysis commands are off for it.
+    let auto_orient = auto_orient.unwrap_or(true);
+    let roi = crop.map(|crop| Roi {
+        crop,
+        rotation,
+        auto_orient,
+    });
+    let generation = state.load_generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let decode_path = path.to_string();
+    let preview = tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let _decoding = state.decode_lock.lock().unwrap();
+        if state.load_generation.load(Ordering::SeqCst) != generation {
+            return Err(AppError::Cancelled);
+        }
+        let preview = process_libraw_timed(
+            &decode_path,
+            Some(target),
+            false,
+            options,
+            roi.as_ref(),
+            &mut ExportStats::default(),
+        )?;
+        let degrees =
+            normalize_rotation(display_rotation(rotation, auto_orient, preview.orientation))
+                .map_err(AppError::InvalidInput)?;
+        let mut preview = if degrees == 0 {
+            preview
+        } else {
+            rotate_context(&preview, degrees)
+        };
+        preview.roi = crop;
+        Ok(preview)
+    })
+    .await
+    .map_err(|e| AppError::Decode(e.to_string()))??;
+
+    let result = ImageResult::from_context(&preview, preview.data.clone());
+    {
+        // Checked under the lock so a superseded load can't overwrite a newer one
+        let mut context = state.preview_context.lock().unwrap();
+        if state.load_generation.load(Ordering::SeqCst) != generation {
+            return Err(AppError::Cancelled);
+        }
+        *context = Some(preview);
+    }
+    state.merge_paths.lock().unwrap().clear();
+    let previous = state.current_path.lock().unwrap().replace(path.to_string());
+    // Edit history belongs to one file; reloading the same file keeps it
+    if previous.as_deref() != Some(path) {
+        *state.params_history.lock().unwrap() = ParamsHistory::default();
+    }
+    // A new file invalidates the export cache
+    *state.full_res_cache.lock().unwrap() = None;
+    result.into_response().map_err(AppError::Encode)
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_dialog::init())
-        .manage(AppState {
-            preview_context: Mutex::new(None),
-        })
-        .invoke_handler(tauri::generate_handler![
-            load_raw,
-            export_image,
-            save_params,
-            load_params
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+// Decodes same-sized frames of one sensor and averages them. The frames are
+// not registered, so they must already be aligned.
+fn merge_frames(
+    paths: &[String],
+    target_dim: Option<usize>,
+    full_quality: bool,
+    options: DecodeOptions,
+    stats: &mut ExportStats,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<PreviewContext, AppError> {
+    let mut merged: Option<(FormatInfo, PreviewContext)> = None;
+    for path in paths {
+        if cancelled() {
+            return Err(AppError::Cancelled);
+        }
+        let info = probe_file(path).map_err(AppError::Decode)?;
+        let frame = process_libraw_timed(path, target_dim, full_quality, options, None, stats)?;
+
+        match merged.as_mut() {
+            None => merged = Some((info, frame)),
+            Some((first_info, sum)) => {
+                if (&info.kind, &info.make, &info.model)
+                    != (&first_info.kind, &first_info.make, &first_info.model)
+                {
+                    return Err(AppError::InvalidInput(format!(
+                        "{} comes from a different sensor than the first frame",
+                        path
+                    )));
+                }
+                if (frame.width, frame.height) != (sum.width, sum.height) {
+                    return Err(AppError::InvalidInput(format!(
+                        "{} is {}x{}, the first frame is {}x{}",
+                        path, frame.width, frame.height, sum.width, sum.height
+                    )));
+                }
+                for (acc, v) in sum.data.iter_mut().zip(&frame.data) {
+                    *acc += v;
+                }
+            }
+        }
+    }
+
+    let (_, mut preview) = merged.ok_or(AppError::InvalidInput("No frames to merge".into()))?;
+    let scale = 1.0 / paths.len() as f32;
+    preview.data.iter_mut().for_each(|v| *v *= scale);
+    Ok(preview)
+}
+
+// Averages several aligned frames of the same scene (tripod bursts) into one
+// preview to cut noise. Frames must come from the same sensor layout and
+// size; they are not registered. Full-resolution regions and exports of the
+// first path average the same frames (see full_res_context).
+#[tauri::command]
+async fn load_raw_merge(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+    rotation: Option<u16>,
+    max_preview_dim: Option<u32>,
+    wb_mode: Option<WbMode>,
+    auto_orient: Option<bool>,
+) -> Result<Response, AppError> {
+    let auto_orient = auto_orient.unwrap_or(true);
+    let first_path = paths
+        .first()
+        .cloned()
+        .ok_or(AppError::InvalidInput("No frames to merge".into()))?;
+    let target = max_preview_dim.unwrap_or(1024) as usize;
+    let options = DecodeOptions {
+        space: OutputSpace::Srgb,
+        wb_mode: wb_mode.unwrap_or_default(),
+        demosaic: DemosaicQuality::default(),
+    };
+    let generation = state.load_generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let frames = paths.clone();
+    let preview = tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let _decoding = state.decode_lock.lock().unwrap();
+        let preview = merge_frames(
+            &frames,
+            Some(target),
+            false,
+            options,
+            &mut ExportStats::default(),
+            &|| state.load_generation.load(Ordering::SeqCst) != generation,
+        )?;
+
+        let degrees =
+            normalize_rotation(display_rotation(rotation, auto_orient, preview.orientation))
+                .map_err(AppError::InvalidInput)?;
+        Ok(if degrees == 0 {
+            preview
+        } else {
+            rotate_context(&preview, degrees)
+        })
+    })
+    .await
+    .map_err(|e| AppError::Decode(e.to_string()))??;
+
+    let result = ImageResult::from_context(&preview, preview.data.clone());
+    {
+        let mut context = state.preview_context.lock().unwrap();
+        if state.load_generation.load(Ordering::SeqCst) != generation {
+            return Err(AppError::Cancelled);
+        }
+        *context = Some(preview);
+    }
+    *state.merge_paths.lock().unwrap() = paths;
+    let previous = state
+        .current_path
+        .lock()
+        .unwrap()
+        .replace(first_path.clone());
+    if previous.as_deref() != Some(first_path.as_str()) {
+        *state.params_history.lock().unwrap() = ParamsHistory::default();
+    }
+    *state.full_res_cache.lock().unwrap() = None;
+    result.into_response().map_err(AppError::Encode)
+}
+
+// Largest resampled preview edge, to keep the IPC payload bounded
+const MAX_RESAMPLE_DIM: u32 = 16384;
+
+// The loaded preview (linear, like load_raw) resampled bilinearly to the
+// display size, so the frontend shows and measures the same pixels
+#[tauri::command]
+fn resample_preview(
+    state: State<AppState>,
+    target_w: u32,
+    target_h: u32,
+) -> Result<Response, AppError> {
+    if target_w == 0 || target_h == 0 || target_w.max(target_h) > MAX_RESAMPLE_DIM {
+        return Err(AppError::InvalidInput(format!(
+            "Invalid preview size {}x{}",
+            target_w, target_h
+        )));
+    }
+    let guard = state.preview_context.lock().unwrap();
+    let preview = guard.as_ref().ok_or(AppError::NoImageLoaded)?;
+
+    let data = filters::resample(
+        &preview.data,
+        preview.width as usize,
+        preview.height as usize,
+        target_w as usize,
+        target_h as usize,
+    );
+    ImageResult {
+        width: target_w,
+        height: target_h,
+        ..ImageResult::from_context(preview, data)
+    }
+    .into_response()
+    .map_err(AppError::Encode)
+}
+
+fn lens_distortion(ctx: &PreviewContext, params: &ImageParams) -> Option<(f32, f32)> {
+    if !params.lens_correction {
+        return None;
+    }
+    ctx.exif
+        .lens
+        .as_deref()
+        .and_then(|lens| lens::distortion_for(lens, ctx.exif.focal_length))
+}
+
+// Whether any pass of prepare_buffer other than denoise is active. Those are
+// placed or sized relative to the whole frame.
+fn frame_passes_active(ctx: &PreviewContext, params: &ImageParams) -> bool {
+    params.clarity != 0.0
+        || params.vignette_correction != 0.0
+        || params.ca_red_scale != 1.0
+        || params.ca_blue_scale != 1.0
+        || params.has_perspective()
+        || params.straighten_angle != 0.0
+        || params.has_local_adjustments()
+        || lens_distortion(ctx, params).is_some()
+        || (params.profile_vignetting && ctx.vignette_profile.is_some())
+}
+
+// prepare_buffer leaves out the frame-relative passes on an ROI preview; the
+// render is then missing adjustments the export will have
+fn roi_skips_passes(ctx: &PreviewContext, params: &ImageParams) -> bool {
+    ctx.roi.is_some() && frame_passes_active(ctx, params)
+}
+
+// Runs the spatial passes (which need neighbouring pixels) over the linear
+// RGBA buffer. Borrows the input untouched when none are active. An ROI
+// preview only gets denoised (see roi_skips_passes).
+fn prepare_buffer<'a>(ctx: &'a PreviewContext, params: &ImageParams) -> Cow<'a, [f32]> {
+    if ctx.roi.is_some() {
+        if params.noise_reduction == 0.0 {
+            return Cow::Borrowed(&ctx.data);
+        }
+        let mut data = ctx.data.clone();
+        filters::apply_luma_denoise(
+            &mut data,
+            ctx.width as usize,
+            ctx.height as usize,
+            params.noise_reduction,
+        );
+        return Cow::Owned(data);
+    }
+    if params.noise_reduction == 0.0 && !frame_passes_active(ctx, params) {
+        return Cow::Borrowed(&ctx.data);
+    }
+
+    let ca = params.ca_red_scale != 1.0 || params.ca_blue_scale != 1.0;
+    let distortion = lens_distortion(ctx, params);
+    let profile_vignette = ctx.vignette_profile.filter(|_| params.profile_vignetting);
+
+    let w = ctx.width as usize;
+    let h = ctx.height as usize;
+    let mut data = ctx.data.clone();
+    // Lens corrections (distortion, CA, vignetting) work on the geometry as shot
+    if let Some(profile) = profile_vignette {
+        profile.apply(&mut data, w, h);
+    }
+    if let Some((k1, k2)) = distortion {
+        data = filters::apply_distortion(&data, w, h, k1, k2);
+    }
+    if ca {
+        filters::apply_lateral_ca(&mut data, w, h, params.ca_red_scale, params.ca_blue_scale);
+    }
+    // Denoise first so later passes don't amplify the noise
+    if params.noise_reduction > 0.0 {
+        filters::apply_luma_denoise(&mut data, w, h, params.noise_reduction);
+    }
+    if params.vignette_correction != 0.0 {
+        filters::apply_vignette(&mut data, w, h, params.vignette_correction);
+    }
+    // Straightening commutes with the 90 degree rotations, perspective doesn't:
+    // callers must rotate first when it's set
+    if params.straighten_angle != 0.0 {
+        data = filters::apply_straighten(&data, w, h, params.straighten_angle);
+    }
+    if params.has_perspective() {
+        data = filters::apply_perspective(
+            &data,
+            w,
+            h,
+            params.perspective_vertical,
+            params.perspective_horizontal,
+        );
+    }
+    // Local adjustments are placed on the corrected geometry
+    if params.has_local_adjustments() {
+        local::apply_local(&mut data, w, h, &params.gradients, &params.radial_filters);
+    }
+    if params.clarity != 0.0 {
+        filters::apply_clarity(&mut data, w, h, params.clarity);
+    }
+    Cow::Owned(data)
+}
+
+// Embedded camera preview for showing something immediately while
+// load_raw runs. Errors when the file has no usable preview.
+#[tauri::command]
+fn load_thumbnail(
+    path: &str,
+    rotation: Option<u16>,
+    auto_orient: Option<bool>,
+) -> Result<Response, AppError> {
+    let thumb = decode_thumbnail(path)?;
+    let degrees = normalize_rotation(display_rotation(
+        rotation,
+        auto_orient.unwrap_or(true),
+        thumb.orientation,
+    ))
+    .map_err(AppError::InvalidInput)?;
+    let mut thumb = if degrees == 0 {
+        thumb
+    } else {
+        rotate_context(&thumb, degrees)
+    };
+
+    let data = std::mem::take(&mut thumb.data);
+    ImageResult::from_context(&thumb, data)
+        .into_response()
+        .map_err(AppError::Encode)
+}
+
+// Sensor layout as far as the decode pipeline is concerned
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum FormatKind {
+    Bayer,
+    XTrans,
+    Linear,     // already demosaiced (linear DNG)
+    FourColor,  // CMYG / RGBE filter arrays
+    Monochrome, // no filter array (e.g. Leica Monochrom)
+    Unsupported,
+}
+
+#[derive(Serialize)]
+struct FormatInfo {
+    make: Option<String>,
+    model: Option<String>,
+    kind: FormatKind,
+    supported: bool,
+    message: Option<String>, // why the file can't be opened
+}
+
+// RAW extensions the libraw decode path handles (Bayer, X-Trans, linear and
+// float DNG). Foveon X3F isn't included: libraw 0.20 dropped it.
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "3fr", "ari", "arw", "bay", "cr2", "cr3", "crw", "dcr", "dng", "erf", "fff", "iiq", "k25",
+    "kdc", "mef", "mos", "mrw", "nef", "nrw", "orf", "pef", "raf", "raw", "rw2", "rwl", "sr2",
+    "srf", "srw",
+];
+
+#[tauri::command]
+fn supported_extensions() -> Vec<String> {
+    SUPPORTED_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+}
+
+// Header-only check (no unpack) so the UI can warn before a full decode.
+// Floating-point data isn't visible until unpack; it's converted to integers
+// there and so needs no separate case.
+#[tauri::command]
+fn probe_file(path: &str) -> Result<FormatInfo, AppError> {
+    // Before libraw_init, so a bad path can't leak the handle
+    let c_path = CString::new(path).map_err(|_| AppError::InvalidInput("Invalid path".into()))?;
+    let _libraw = LIBRAW.lock().unwrap();
+    unsafe {
+        let raw_data = libraw_sys::libraw_init(0);
+        if raw_data.is_null() {
+            return Err(AppError::Decode("Failed to init libraw".into()));
+        }
+
+        let ret = libraw_sys::libraw_open_file(raw_data, c_path.as_ptr());
+        if ret != 0 {
+            let reason = CStr::from_ptr(libraw_sys::libraw_strerror(ret)).to_string_lossy();
+            libraw_sys::libraw_close(raw_data);
+            return Ok(FormatInfo {
+                make: None,
+                model: None,
+                kind: FormatKind::Unsupported,
+                supported: false,
+                message: Some(reason.into_owned()),
+            });
+        }
+
+        let idata = &(*raw_data).idata;
+        let make = c_str_field(&idata.make);
+        let model = c_str_field(&idata.model);
+        let four_color = four_color_cfa(raw_data);
+        let kind = match idata.filters {
+            _ if four_color.is_some() => FormatKind::FourColor,
+            0 if idata.colors == 1 => FormatKind::Monochrome,
+            0 => FormatKind::Linear,
+            9 => FormatKind::XTrans,
+            _ => FormatKind::Bayer,
+        };
+        let message = four_color
+            .filter(|_| !has_color_matrix(raw_data))
+            .map(|cdesc| format!("No colour matrix for this {} sensor", cdesc));
+        libraw_sys::libraw_close(raw_data);
+
+        Ok(FormatInfo {
+            make,
+            model,
+            kind,
+            supported: message.is_none(),
+            message,
+        })
+    }
+}
+
+// Returns the full-quality decode of `path`, decoding only on a cache miss.
+// Blocking: callers run it on the blocking pool. The cache isn't locked
+// during the decode, so readers of other entries don't wait for it; when two
+// callers miss at once, the first decode to finish is kept.
+fn full_res_context(
+    state: &AppState,
+    path: &str,
+    options: DecodeOptions,
+    stats: &mut ExportStats,
+) -> Result<Arc<PreviewContext>, AppError> {
+    let cached = |cache: &Option<(String, DecodeOptions, Arc<PreviewContext>)>| {
+        cache
+            .as_ref()
+            .filter(|(cached_path, cached_options, _)| {
+                cached_path == path && *cached_options == options
+            })
+            .map(|(_, _, ctx)| ctx.clone())
+    };
+    if let Some(ctx) = cached(&state.full_res_cache.lock().unwrap()) {
+        return Ok(ctx);
+    }
+
+    let frames = state.merge_paths.lock().unwrap().clone();
+    let ctx = Arc::new(if frames.first().map(String::as_str) == Some(path) {
+        merge_frames(&frames, None, true, options, stats, &|| false)?
+    } else {
+        process_libraw_timed(path, None, true, options, None, stats)?
+    });
+
+    let mut cache = state.full_res_cache.lock().unwrap();
+    if let Some(ctx) = cached(&cache) {
+        return Ok(ctx);
+    }
+    *cache = Some((path.to_string(), options, ctx.clone()));
+    Ok(ctx)
+}
+
+// Emits `export-progress` (0..=100) at most once per whole percent
+struct ExportProgress<'a> {
+    app: &'a AppHandle,
+    // The export's number (see AppState::export_generation)
+    generation: u64,
+    total_rows: u32,
+    last_percent: Option<u32>,
+}
+
+impl<'a> ExportProgress<'a> {
+    fn new(app: &'a AppHandle, generation: u64) -> Self {
+        ExportProgress {
+            app,
+            generation,
+            total_rows: 1,
+            last_percent: None,
+        }
+    }
+
+    fn start(&mut self, total_rows: u32) {
+        self.total_rows = total_rows.max(1);
+        self.last_percent = None;
+        self.report(0);
+    }
+
+    fn report(&mut self, percent: u32) {
+        if self.last_percent != Some(percent) {
+            self.last_percent = Some(percent);
+            let _ = self.app.emit("export-progress", percent);
+        }
+    }
+
+    // Also the cancellation point: errors once cancel_export was called
+    fn row(&mut self, y: u32) -> Result<(), AppError> {
+        if export_cancelled(&self.app.state::<AppState>(), self.generation) {
+            return Err(AppError::Cancelled);
+        }
+        self.report(y * 100 / self.total_rows);
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        self.report(100);
+    }
+}
+
+// Grain seed derived from the params (FNV-1a over their JSON), so exports
+// with identical settings get identical grain
+fn grain_seed(params: &ImageParams) -> Result<u64, String> {
+    let json = params_json(params)?;
+    Ok(json.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    }))
+}
+
+// Exports above this many output pixels are streamed by default: no output
+// buffer, and the full-resolution decode isn't kept cached afterwards (see
+// render_export for what is still whole-image)
+const DEFAULT_STREAM_ABOVE_PX: u64 = 50_000_000;
+// Rows processed and written per strip when streaming
+const STRIP_ROWS: u32 = 64;
+
+// Output settings shared by single and batch exports
+struct RenderOptions {
+    quality: Option<u8>,
+    space: OutputSpace,
+    resize_long_edge: Option<u32>,
+    stream_above_px: u64,
+    // Ordered dithering for 8-bit output (16-bit output is never dithered)
+    dither: bool,
+    // EXR only: apply the adjustments (still linear) or write the decode as-is
+    hdr_adjustments: bool,
+    // Clockwise degrees to rotate the decode by, instead of the params'
+    // orientation (which is still what gets embedded in the file)
+    rotation: Option<u16>,
+}
+
+// 8x8 Bayer matrix for ordered dithering
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// 8-bit code value, rounded to nearest. Dithering instead adds a fixed
+// threshold per pixel position (averaging 0.5) before truncating, which breaks
+// up banding in smooth gradients while keeping exports reproducible.
+fn quantize_u8(v: f32, x: u32, y: u32, dither: bool) -> u8 {
+    let v = v.clamp(0.0, 1.0) * 255.0;
+    let t = if dither {
+        (BAYER_8X8[y as usize % 8][x as usize % 8] as f32 + 0.5) / 64.0
+    } else {
+        0.5
+    };
+    (v + t).min(255.0) as u8
+}
+
+fn quantize_u16(v: f32) -> u16 {
+    (v.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+// PNG encoder with the params in an iTXt chunk (UTF-8, unlike tEXt, so
+// paths such as apply_lut can hold any character). The colour space is
+// tagged with sRGB, or gAMA and cHRM for the wide-gamut spaces.
+fn png_encoder(
+    file: File,
+    w: u32,
+    h: u32,
+    params: &ImageParams,
+    space: OutputSpace,
+    alpha: bool,
+) -> Result<png::Encoder<'static, BufWriter<File>>, AppError> {
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w, h);
+    encoder.set_color(if alpha {
+        png::ColorType::Rgba
+    } else {
+        png::ColorType::Rgb
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    if space == OutputSpace::Srgb {
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    } else {
+        let [white, red, green, blue] = space.chromaticities();
+        encoder.set_source_gamma(png::ScaledFloat::new(1.0 / space.gamma()));
+        encoder.set_source_chromaticities(png::SourceChromaticities::new(white, red, green, blue));
+    }
+    encoder
+        .add_itxt_chunk(
+            PARAMS_TEXT_KEY.to_string(),
+            embedded_params_json(params, space)?,
+        )
+        .map_err(encode_error)?;
+    Ok(encoder)
+}
+
+// TIFF WhitePoint and PrimaryChromaticities of the output space
+fn write_tiff_chromaticities<W: Write + Seek, K: TiffKind>(
+    dir: &mut DirectoryEncoder<W, K>,
+    space: OutputSpace,
+) -> Result<(), AppError> {
+    let rational = |v: f32| Rational {
+        n: (v * 1_000_000.0).round() as u32,
+        d: 1_000_000,
+    };
+    let [white, red, green, blue] = space.chromaticities();
+    let primaries: Vec<Rational> = [red, green, blue]
+        .iter()
+        .flat_map(|&(x, y)| [rational(x), rational(y)])
+        .collect();
+    dir.write_tag(
+        TIFF_WHITE_POINT,
+        &[rational(white.0), rational(white.1)][..],
+    )
+    .map_err(encode_error)?;
+    dir.write_tag(TIFF_PRIMARY_CHROMATICITIES, &primaries[..])
+        .map_err(encode_error)
+}
+
+// Not named by the tiff crate
+const TIFF_WHITE_POINT: Tag = Tag::Unknown(318);
+const TIFF_PRIMARY_CHROMATICITIES: Tag = Tag::Unknown(319);
+
+// Lossy WebP goes through libwebp, which the image crate only builds with
+// its `webp-encoder` feature (enabled by this crate's `webp` feature)
+#[cfg(feature = "webp")]
+fn write_webp(
+    imgbuf: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    save_path: &str,
+    quality: Option<u8>,
+) -> Result<(), AppError> {
+    use image::codecs::webp::{WebPEncoder, WebPQuality};
+
+    let file = File::create(save_path)?;
+    let quality = WebPQuality::lossy(quality.unwrap_or(90).clamp(1, 100));
+    WebPEncoder::new_with_quality(BufWriter::new(file), quality)
+        .encode(
+            imgbuf.as_raw(),
+            imgbuf.width(),
+            imgbuf.height(),
+            image::ColorType::Rgb8,
+        )
+        .map_err(encode_error)
+}
+
+#[cfg(not(feature = "webp"))]
+fn write_webp(
+    _imgbuf: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    _save_path: &str,
+    _quality: Option<u8>,
+) -> Result<(), AppError> {
+    Err(AppError::UnsupportedFormat(
+        "WebP export is not available in this build (enable the `webp` feature)".into(),
+    ))
+}
+
+// Whether the geometric passes left undefined (alpha 0) pixels in the crop
+fn has_transparency(source: &Rotated, crop: (u32, u32, u32, u32)) -> bool {
+    let (crop_x, crop_y, out_w, out_h) = crop;
+    (crop_y..crop_y + out_h).any(|y| (crop_x..crop_x + out_w).any(|x| source.pixel(x, y)[3] < 1.0))
+}
+
+// Rotation, spatial passes, crop, per-pixel processing and encoding of an
+// already decoded image. Shared by the single and batch exporters; reports
+// the final 100% progress itself.
+fn render_export(
+    decoded: &PreviewContext,
+    params: &ImageParams,
+    save_path: &str,
+    options: &RenderOptions,
+    progress: &mut ExportProgress,
+) -> Result<ExportStats, AppError> {
+    let space = options.space;
+    let mut stats = ExportStats::default();
+    let process_start = Instant::now();
+    let degrees = normalize_rotation(options.rotation.unwrap_or(display_rotation(
+        params.rotation,
+        params.auto_orient,
+        decoded.orientation,
+    )))
+    .map_err(AppError::InvalidInput)?;
+    let params = params.for_context(decoded);
+    let params = &*params;
+    // The rotation is read in place (see Rotated) rather than copied, unless
+    // perspective or local masks need the display orientation up front
+    let rotated;
+    let (processed, degrees): (&PreviewContext, u16) =
+        if degrees != 0 && params.needs_display_orientation() {
+            rotated = rotate_context(decoded, degrees);
+            (&rotated, 0)
+        } else {
+            (decoded, degrees)
+        };
+    let (w, h) = if degrees % 180 == 0 {
+        (processed.width, processed.height)
+    } else {
+        (processed.height, processed.width)
+    };
+
+    // An ROI decode is the crop already
+    let crop = params.crop.as_ref().filter(|_| decoded.roi.is_none());
+    let (crop_x, crop_y, out_w, out_h) = crop_to_pixels(crop, w, h);
+
+    // Output bit depth follows the extension: TIFF gets 16 bits, everything else 8
+    let ext = Path::new(save_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let sixteen_bit = ext == "tif" || ext == "tiff";
+    // Before the spatial passes and LUT load below, which EXR does itself or skips
+    if ext == "exr" {
+        let stats = write_exr(
+            processed,
+            degrees,
+            params,
+            save_path,
+            options,
+            progress,
+            (crop_x, crop_y, out_w, out_h),
+        )?;
+        progress.finish();
+        return Ok(stats);
+    }
+
+    let data = prepare_buffer(processed, params);
+    let source = Rotated {
+        data: &data,
+        width: processed.width as usize,
+        height: processed.height as usize,
+        degrees,
+    };
+    let lut = params.lut().map_err(AppError::InvalidInput)?;
+    let grade = |px: &[f32]| {
+        let (r, g, b) = apply_processing_in(px[0], px[1], px[2], params, space);
+        match &lut {
+            Some(lut) => lut.apply([r, g, b]),
+            None => [r, g, b],
+        }
+    };
+    // Empty corners from straighten/perspective become transparent in PNGs;
+    // other formats keep them black
+    let transparent = ext == "png" && has_transparency(&source, (crop_x, crop_y, out_w, out_h));
+    let channels = if transparent { 4 } else { 3 };
+    progress.start(out_h);
+
+    // Large exports without whole-image post passes (resize, sharpening,
+    // grain) are toned and written a strip at a time, so no full-size output
+    // buffer is held. JPEG has no row-wise encoder here and always buffers.
+    // What stays full size is the decode, plus one prepared copy when spatial
+    // passes are active (and a rotated one when perspective or local masks
+    // are): the geometric passes sample anywhere in the frame, so they aren't
+    // run in bands.
+    let resizing = options
+        .resize_long_edge
+        .is_some_and(|t| t > 0 && t < out_w.max(out_h));
+    let streamable = (sixteen_bit || ext == "png")
+        && !resizing
+        && params.sharpen_amount <= 0.0
+        && params.grain_amount <= 0.0;
+    if streamable && out_w as u64 * out_h as u64 > options.stream_above_px {
+        let tone_row = |y: u32, row: &mut Vec<f32>| {
+            row.clear();
+            for x in crop_x..crop_x + out_w {
+                let px = source.pixel(x, y + crop_y);
+                row.extend_from_slice(&grade(px));
+                if transparent {
+                    row.push(px[3]);
+                }
+            }
+        };
+        let mut row = Vec::with_capacity(out_w as usize * channels);
+        let file = File::create(save_path)?;
+        // A cancelled or failed stream leaves a truncated file behind; remove it
+        let written = (|| -> Result<(), AppError> {
+            if sixteen_bit {
+                let mut encoder = TiffEncoder::new(BufWriter::new(file)).map_err(encode_error)?;
+                let mut image = encoder
+                    .new_image::<colortype::RGB16>(out_w, out_h)
+                    .map_err(encode_error)?;
+                image.rows_per_strip(STRIP_ROWS).map_err(encode_error)?;
+                image
+                    .encoder()
+                    .write_tag(
+                        Tag::ImageDescription,
+                        embedded_params_json(params, space)?.as_str(),
+                    )
+                    .map_err(encode_error)?;
+                write_tiff_chromaticities(image.encoder(), space)?;
+
+                let mut strip: Vec<u16> = Vec::with_capacity(row.capacity() * STRIP_ROWS as usize);
+                for y0 in (0..out_h).step_by(STRIP_ROWS as usize) {
+                    strip.clear();
+                    for y in y0..(y0 + STRIP_ROWS).min(out_h) {
+                        progress.row(y)?;
+                        tone_row(y, &mut row);
+                        strip.extend(row.iter().map(|&v| quantize_u16(v)));
+                    }
+                    image.write_strip(&strip).map_err(encode_error)?;
+                }
+                image.finish().map_err(encode_error)?;
+            } else {
+                let mut writer = png_encoder(file, out_w, out_h, params, space, transparent)?
+                    .write_header()
+                    .map_err(encode_error)?;
+                let mut stream = writer.stream_writer().map_err(encode_error)?;
+
+                let mut bytes: Vec<u8> = Vec::with_capacity(row.capacity());
+                for y in 0..out_h {
+                    progress.row(y)?;
+                    tone_row(y, &mut row);
+                    bytes.clear();
+                    bytes.extend(
+                        row.iter().enumerate().map(|(i, &v)| {
+                            quantize_u8(v, (i / channels) as u32, y, options.dither)
+                        }),
+                    );
+                    stream.write_all(&bytes)?;
+                }
+                stream.finish().map_err(encode_error)?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(save_path);
+            return Err(e);
+        }
+
+        // Processing and encoding are interleaved, so it's all process time
+        stats.process_ms = elapsed_ms(process_start);
+        progress.finish();
+        return Ok(stats);
+    }
+
+    // Tone-mapped, gamma-encoded output (RGBA stride so the filters apply)
+    let mut out = vec![0.0f32; out_w as usize * out_h as usize * 4];
+    for (i, px) in out.chunks_exact_mut(4).enumerate() {
+        let x = i as u32 % out_w;
+        let y = i as u32 / out_w;
+        if x == 0 {
+            progress.row(y)?;
+        }
+        let src = source.pixel(x + crop_x, y + crop_y);
+        let [r_out, g_out, b_out] = grade(src);
+        px[0] = r_out;
+        px[1] = g_out;
+        px[2] = b_out;
+        px[3] = src[3];
+    }
+
+    // Downscale for sharing; never upscales. Aspect ratio is kept.
+    let (out_w, out_h, mut out) = match options.resize_long_edge {
+        Some(target) if target > 0 && target < out_w.max(out_h) => {
+            let scale = target as f32 / out_w.max(out_h) as f32;
+            let new_w = ((out_w as f32 * scale).round() as u32).max(1);
+            let new_h = ((out_h as f32 * scale).round() as u32).max(1);
+            let full: ImageBuffer<Rgba<f32>, Vec<f32>> =
+                ImageBuffer::from_raw(out_w, out_h, out)
+                    .ok_or_else(|| AppError::Encode("Invalid export buffer".into()))?;
+            let resized = imageops::resize(&full, new_w, new_h, FilterType::Lanczos3);
+            (new_w, new_h, resized.into_raw())
+        }
+        _ => (out_w, out_h, out),
+    };
+
+    // Sharpening needs neighbouring output pixels, so it runs after tone
+    // mapping (and after resizing, at the output resolution)
+    if params.sharpen_amount > 0.0 {
+        let radius = if params.sharpen_radius > 0.0 {
+            params.sharpen_radius
+        } else {
+            1.0
+        };
+        filters::apply_unsharp_mask(
+            &mut out,
+            out_w as usize,
+            out_h as usize,
+            params.sharpen_amount,
+            radius,
+        );
+    }
+
+    // Grain goes on last so sharpening doesn't exaggerate it
+    if params.grain_amount > 0.0 {
+        filters::apply_grain(
+            &mut out,
+            out_w as usize,
+            out_h as usize,
+            params.grain_amount,
+            params.grain_size,
+            grain_seed(params).map_err(AppError::Encode)?,
+        );
+    }
+
+    stats.process_ms = elapsed_ms(process_start);
+    let encode_start = Instant::now();
+
+    if sixteen_bit {
+        let mut imgbuf: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::new(out_w, out_h);
+
+        for (pixel, px) in imgbuf.pixels_mut().zip(out.chunks_exact(4)) {
+            let r16 = quantize_u16(px[0]);
+            let g16 = quantize_u16(px[1]);
+            let b16 = quantize_u16(px[2]);
+
+            *pixel = Rgb([r16, g16, b16]);
+        }
+
+        // Written with the tiff crate directly so the params can go in ImageDescription
+        let file = File::create(save_path)?;
+        let mut encoder = TiffEncoder::new(BufWriter::new(file)).map_err(encode_error)?;
+        let mut image = encoder
+            .new_image::<colortype::RGB16>(out_w, out_h)
+            .map_err(encode_error)?;
+        image
+            .encoder()
+            .write_tag(
+                Tag::ImageDescription,
+                embedded_params_json(params, space)?.as_str(),
+            )
+            .map_err(encode_error)?;
+        write_tiff_chromaticities(image.encoder(), space)?;
+        image.write_data(imgbuf.as_raw()).map_err(encode_error)?;
+    } else if transparent {
+        let mut imgbuf: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(out_w, out_h);
+
+        for ((x, y, pixel), px) in imgbuf.enumerate_pixels_mut().zip(out.chunks_exact(4)) {
+            *pixel = Rgba([
+                quantize_u8(px[0], x, y, options.dither),
+                quantize_u8(px[1], x, y, options.dither),
+                quantize_u8(px[2], x, y, options.dither),
+                quantize_u8(px[3], x, y, false),
+            ]);
+        }
+
+        let file = File::create(save_path)?;
+        let mut writer = png_encoder(file, out_w, out_h, params, space, true)?
+            .write_header()
+            .map_err(encode_error)?;
+        writer
+            .write_image_data(imgbuf.as_raw())
+            .map_err(encode_error)?;
+    } else {
+        let mut imgbuf: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(out_w, out_h);
+
+        for ((x, y, pixel), px) in imgbuf.enumerate_pixels_mut().zip(out.chunks_exact(4)) {
+            let r8 = quantize_u8(px[0], x, y, options.dither);
+            let g8 = quantize_u8(px[1], x, y, options.dither);
+            let b8 = quantize_u8(px[2], x, y, options.dither);
+
+            *pixel = Rgb([r8, g8, b8]);
+        }
+
+        // JPEG and WebP carry no colour tags, so viewers assume sRGB
+        if ext == "jpg" || ext == "jpeg" {
+            let file = File::create(save_path)?;
+            let mut writer = BufWriter::new(file);
+            let mut encoder = JpegEncoder::new_with_quality(
+                &mut writer,
+                options.quality.unwrap_or(90).clamp(1, 100),
+            );
+            encoder.encode_image(&imgbuf).map_err(encode_error)?;
+        } else if ext == "webp" {
+            write_webp(&imgbuf, save_path, options.quality)?;
+        } else if ext == "png" {
+            let file = File::create(save_path)?;
+            let mut writer = png_encoder(file, out_w, out_h, params, space, false)?
+                .write_header()
+                .map_err(encode_error)?;
+            writer
+                .write_image_data(imgbuf.as_raw())
+                .map_err(encode_error)?;
+        } else {
+            imgbuf.save(save_path).map_err(encode_error)?;
+        }
+    }
+    stats.encode_ms = elapsed_ms(encode_start);
+    progress.finish();
+    Ok(stats)
+}
+
+// Scene-linear 32-bit float OpenEXR. Applied: rotation, crop and resize,
+// plus (with hdr_adjustments) the spatial passes and apply_layers, whose
+// output is never clipped above 1. Always bypassed: gamut compression, tone
+// mapping, output gamma, 3D LUT, sharpening and grain, which are
+// display-referred. Without
+// hdr_adjustments the decoded linear RGB is written untouched.
+fn write_exr(
+    processed: &PreviewContext,
+    degrees: u16,
+    params: &ImageParams,
+    save_path: &str,
+    options: &RenderOptions,
+    progress: &mut ExportProgress,
+    crop: (u32, u32, u32, u32),
+) -> Result<ExportStats, AppError> {
+    let mut stats = ExportStats::default();
+    let process_start = Instant::now();
+    let (crop_x, crop_y, out_w, out_h) = crop;
+    let data = if options.hdr_adjustments {
+        prepare_buffer(processed, params)
+    } else {
+        Cow::Borrowed(&processed.data[..])
+    };
+    let source = Rotated {
+        data: &data,
+        width: processed.width as usize,
+        height: processed.height as usize,
+        degrees,
+    };
+
+    progress.start(out_h);
+    let mut out = Vec::with_capacity(out_w as usize * out_h as usize * 3);
+    for y in 0..out_h {
+        progress.row(y)?;
+        for x in crop_x..crop_x + out_w {
+            let px = source.pixel(x, y + crop_y);
+            if options.hdr_adjustments {
+                out.extend_from_slice(&apply_layers(px[0], px[1], px[2], params));
+            } else {
+                out.extend_from_slice(&px[..3]);
+            }
+        }
+    }
+    let mut imgbuf: ImageBuffer<Rgb<f32>, Vec<f32>> = ImageBuffer::from_raw(out_w, out_h, out)
+        .ok_or_else(|| AppError::Encode("Invalid export buffer".into()))?;
+    if let Some(target) = options.resize_long_edge {
+        if target > 0 && target < out_w.max(out_h) {
+            let scale = target as f32 / out_w.max(out_h) as f32;
+            let new_w = ((out_w as f32 * scale).round() as u32).max(1);
+            let new_h = ((out_h as f32 * scale).round() as u32).max(1);
+            // Triangle rather than Lanczos: no ringing around bright highlights
+            imgbuf = imageops::resize(&imgbuf, new_w, new_h, FilterType::Triangle);
+        }
+    }
+    stats.process_ms = elapsed_ms(process_start);
+
+    let encode_start = Instant::now();
+    imgbuf.save(save_path).map_err(encode_error)?;
+    stats.encode_ms = elapsed_ms(encode_start);
+    Ok(stats)
+}
+
+// async with the decode and render on the blocking pool, so the main thread
+// and the async runtime stay free and progress events reach the UI
+#[tauri::command]
+async fn export_image(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: &str,
+    params: ImageParams,
+    save_path: &str,
+    full_quality: Option<bool>,
+    quality: Option<u8>,
+    output_space: Option<OutputSpace>,
+    resize_long_edge: Option<u32>,
+    profile: Option<bool>,
+    stream_above_px: Option<u64>,
+    demosaic: Option<DemosaicQuality>,
+    dither: Option<bool>,
+    hdr_adjustments: Option<bool>,
+) -> Result<Option<ExportStats>, AppError> {
+    let generation = start_export(&state);
+    let path = path.to_string();
+    let save_path = save_path.to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let space = output_space.unwrap_or_default();
+        let options = DecodeOptions {
+            space,
+            wb_mode: params.wb_mode,
+            demosaic: demosaic.unwrap_or_default(),
+        };
+        let mut stats = ExportStats::default();
+        // Full Export: No target width (Full Res)
+        let decoded = if full_quality.unwrap_or(true) {
+            full_res_context(&state, &path, options, &mut stats)?
+        } else {
+            Arc::new(process_libraw_timed(
+                &path, None, false, options, None, &mut stats,
+            )?)
+        };
+        if export_cancelled(&state, generation) {
+            return Err(AppError::Cancelled);
+        }
+        let mut progress = ExportProgress::new(&app, generation);
+        let render_options = RenderOptions {
+            quality,
+            space,
+            resize_long_edge,
+            stream_above_px: stream_above_px.unwrap_or(DEFAULT_STREAM_ABOVE_PX),
+            dither: dither.unwrap_or(true),
+            hdr_adjustments: hdr_adjustments.unwrap_or(true),
+            rotation: None,
+        };
+        let rendered = render_export(
+            &decoded,
+            &params,
+            &save_path,
+            &render_options,
+            &mut progress,
+        );
+        release_large_decode(&state, &decoded, render_options.stream_above_px);
+        let render_stats = rendered.map_err(|e| export_error(&state, generation, e))?;
+
+        if !profile.unwrap_or(false) {
+            return Ok(None);
+        }
+        stats.process_ms = render_stats.process_ms;
+        stats.encode_ms = render_stats.encode_ms;
+        Ok(Some(stats))
+    })
+    .await
+    .map_err(|e| AppError::Decode(e.to_string()))?
+}
+
+// Quick export of the loaded preview (no full-resolution decode), for
+// sharing. Format follows the extension as in export_image; output is sRGB
+// like the preview decode.
+#[tauri::command]
+fn export_preview(
+    app: AppHandle,
+    state: State<AppState>,
+    params: ImageParams,
+    save_path: &str,
+    quality: Option<u8>,
+    resize_long_edge: Option<u32>,
+    dither: Option<bool>,
+) -> Result<(), AppError> {
+    let generation = start_export(&state);
+    let preview = state
+        .preview_context
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or(AppError::NoImageLoaded)?;
+    let render_options = RenderOptions {
+        quality,
+        space: OutputSpace::Srgb,
+        resize_long_edge,
+        stream_above_px: DEFAULT_STREAM_ABOVE_PX,
+        dither: dither.unwrap_or(true),
+        hdr_adjustments: true,
+        // The preview is already in display orientation
+        rotation: Some(0),
+    };
+
+    let mut progress = ExportProgress::new(&app, generation);
+    render_export(&preview, &params, save_path, &render_options, &mut progress)
+        .map_err(|e| export_error(&state, generation, e))?;
+    Ok(())
+}
+
+// Demosaiced but ungraded image as a 16-bit linear TIFF (no tone curve,
+// adjustments or gamma) for finishing in another editor. Only the decode
+// settings (WB mode, output primaries) and the orientation and crop from
+// params are applied. Runs on the blocking pool like export_image.
+#[tauri::command]
+async fn export_linear_tiff(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: &str,
+    params: ImageParams,
+    save_path: &str,
+    output_space: Option<OutputSpace>,
+    demosaic: Option<DemosaicQuality>,
+) -> Result<(), AppError> {
+    let generation = start_export(&state);
+    let path = path.to_string();
+    let save_path = save_path.to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let space = output_space.unwrap_or_default();
+        let options = DecodeOptions {
+            space,
+            wb_mode: params.wb_mode,
+            demosaic: demosaic.unwrap_or_default(),
+        };
+        let decoded = full_res_context(&state, &path, options, &mut ExportStats::default())?;
+        if export_cancelled(&state, generation) {
+            return Err(AppError::Cancelled);
+        }
+
+        let degrees = normalize_rotation(display_rotation(
+            params.rotation,
+            params.auto_orient,
+            decoded.orientation,
+        ))
+        .map_err(AppError::InvalidInput)?;
+        let source = Rotated {
+            data: &decoded.data,
+            width: decoded.width as usize,
+            height: decoded.height as usize,
+            degrees,
+        };
+        let (w, h) = source.dimensions();
+        let (crop_x, crop_y, out_w, out_h) = crop_to_pixels(params.crop.as_ref(), w, h);
+        let description = linear_tiff_description(&params, space)?;
+
+        let mut progress = ExportProgress::new(&app, generation);
+        progress.start(out_h);
+        let file = File::create(&save_path)?;
+        let written = (|| -> Result<(), AppError> {
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).map_err(encode_error)?;
+            let mut image = encoder
+                .new_image::<colortype::RGB16>(out_w, out_h)
+                .map_err(encode_error)?;
+            image.rows_per_strip(STRIP_ROWS).map_err(encode_error)?;
+            image
+                .encoder()
+                .write_tag(Tag::ImageDescription, description.as_str())
+                .map_err(encode_error)?;
+            write_tiff_chromaticities(image.encoder(), space)?;
+
+            let mut strip: Vec<u16> = Vec::with_capacity((out_w * STRIP_ROWS * 3) as usize);
+            for y0 in (0..out_h).step_by(STRIP_ROWS as usize) {
+                strip.clear();
+                for y in y0..(y0 + STRIP_ROWS).min(out_h) {
+                    progress.row(y)?;
+                    for x in crop_x..crop_x + out_w {
+                        let px = source.pixel(x, y + crop_y);
+                        strip.extend(px[..3].iter().map(|&v| quantize_u16(v)));
+                    }
+                }
+                image.write_strip(&strip).map_err(encode_error)?;
+            }
+            image.finish().map_err(encode_error)
+        })();
+        release_large_decode(&state, &decoded, DEFAULT_STREAM_ABOVE_PX);
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&save_path);
+            return Err(export_error(&state, generation, e));
+        }
+        progress.finish();
+        Ok(())
+    })
+    .await
+    .map_err(|e| AppError::Decode(e.to_string()))?
+}
+
+// Drops the cached full-resolution decode after exporting an image above the
+// streaming threshold, so its buffer goes with the export's instead of being
+// held for the session (the next region or export decodes again)
+fn release_large_decode(state: &AppState, decoded: &PreviewContext, stream_above_px: u64) {
+    if decoded.width as u64 * decoded.height as u64 > stream_above_px {
+        *state.full_res_cache.lock().unwrap() = None;
+    }
+}
+
+// Number of a new export, for export_cancelled
+fn start_export(state: &AppState) -> u64 {
+    state.export_generation.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+fn export_cancelled(state: &AppState, generation: u64) -> bool {
+    state.export_cancelled.load(Ordering::SeqCst) >= generation
+}
+
+// A render error after cancel_export is the cancellation itself
+fn export_error(state: &AppState, generation: u64, e: AppError) -> AppError {
+    if export_cancelled(state, generation) {
+        AppError::Cancelled
+    } else {
+        e
+    }
+}
+
+// Encoder failures (bad dimensions, codec errors) as opposed to I/O
+fn encode_error(e: impl ToString) -> AppError {
+    AppError::Encode(e.to_string())
+}
+
+// Stops the running exports and batches at the next row. A partially
+// streamed file is removed; a batch keeps the files it already finished.
+#[tauri::command]
+fn cancel_export(state: State<AppState>) {
+    let latest = state.export_generation.load(Ordering::SeqCst);
+    state.export_cancelled.fetch_max(latest, Ordering::SeqCst);
+}
+
+#[derive(Serialize)]
+struct BatchItemResult {
+    source: String,
+    output: Option<String>,
+    error: Option<String>,
+}
+
+// Output path in out_dir named after the source stem, with a numeric suffix
+// when two inputs share a stem
+fn batch_output_path(source: &str, out_dir: &str, format: &str, used: &mut Vec<String>) -> String {
+    let stem = Path::new(source)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("export");
+    let mut name = format!("{}.{}", stem, format);
+    let mut n = 1;
+    while used.contains(&name) {
+        name = format!("{}_{}.{}", stem, n, format);
+        n += 1;
+    }
+    used.push(name.clone());
+    Path::new(out_dir).join(name).to_string_lossy().into_owned()
+}
+
+// Applies one set of params to many files. Failures are reported per file
+// and don't stop the batch. Runs on the blocking pool. Files are decoded one
+// at a time: the linked libraw is the non-thread-safe build (see
+// pkgconfig/libraw.pc), and process_libraw holds LIBRAW.
+#[tauri::command]
+async fn export_batch(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+    params: ImageParams,
+    out_dir: &str,
+    format: String,
+    quality: Option<u8>,
+    output_space: Option<OutputSpace>,
+    resize_long_edge: Option<u32>,
+    dither: Option<bool>,
+) -> Result<Vec<BatchItemResult>, AppError> {
+    let generation = start_export(&state);
+    std::fs::create_dir_all(out_dir)?;
+    let format = format.trim_start_matches('.').to_ascii_lowercase();
+    let space = output_space.unwrap_or_default();
+    let options = DecodeOptions {
+        space,
+        wb_mode: params.wb_mode,
+        demosaic: DemosaicQuality::default(),
+    };
+    let render_options = RenderOptions {
+        quality,
+        space,
+        resize_long_edge,
+        stream_above_px: DEFAULT_STREAM_ABOVE_PX,
+        dither: dither.unwrap_or(true),
+        hdr_adjustments: true,
+        rotation: None,
+    };
+
+    let out_dir = out_dir.to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let mut used = Vec::new();
+        let mut results = Vec::with_capacity(paths.len());
+        for (i, source) in paths.iter().enumerate() {
+            if export_cancelled(&state, generation) {
+                return Err(AppError::Cancelled);
+            }
+            let _ = app.emit("batch-progress", (i, paths.len()));
+            let save_path = batch_output_path(source, &out_dir, &format, &mut used);
+
+            let mut progress = ExportProgress::new(&app, generation);
+            let outcome = process_libraw(source, None, true, options).and_then(|decoded| {
+                render_export(
+                    &decoded,
+                    &params,
+                    &save_path,
+                    &render_options,
+                    &mut progress,
+                )
+            });
+            if outcome.is_err() && export_cancelled(&state, generation) {
+                return Err(AppError::Cancelled);
+            }
+
+            results.push(match outcome {
+                Ok(_) => BatchItemResult {
+                    source: source.clone(),
+                    output: Some(save_path),
+                    error: None,
+                },
+                Err(e) => BatchItemResult {
+                    source: source.clone(),
+                    output: None,
+                    error: Some(e.to_string()),
+                },
+            });
+        }
+        let _ = app.emit("batch-progress", (paths.len(), paths.len()));
+
+        Ok(results)
+    })
+    .await
+    .map_err(|e| AppError::Decode(e.to_string()))?
+}
+
+// 100% zoom: processes a rectangle of the full-resolution image of the
+// loaded file. x/y/w/h are full-res pixels in the rotated orientation.
+// Unlike load_raw, `data` holds processed (display-encoded) RGB. async with
+// the work on the blocking pool: a cache miss is a full-resolution decode.
+#[tauri::command]
+async fn get_region(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    params: ImageParams,
+) -> Result<Response, String> {
+    let path = state
+        .current_path
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or(AppError::NoImageLoaded)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let options = DecodeOptions {
+            space: OutputSpace::Srgb,
+            wb_mode: params.wb_mode,
+            demosaic: DemosaicQuality::default(),
+        };
+        let full = full_res_context(&state, &path, options, &mut ExportStats::default())?;
+
+        let mut degrees = normalize_rotation(display_rotation(
+            params.rotation,
+            params.auto_orient,
+            full.orientation,
+        ))?;
+        // Perspective and local masks aren't rotation-invariant, so rotate up
+        // front in that case
+        let full = if degrees != 0 && params.needs_display_orientation() {
+            let rotated = Arc::new(rotate_context(&full, degrees));
+            degrees = 0;
+            rotated
+        } else {
+            full
+        };
+        let src_w = full.width as usize;
+        let src_h = full.height as usize;
+        let (rot_w, rot_h) = if degrees % 180 == 0 {
+            (src_w, src_h)
+        } else {
+            (src_h, src_w)
+        };
+
+        let x0 = (x as usize).min(rot_w.saturating_sub(1));
+        let y0 = (y as usize).min(rot_h.saturating_sub(1));
+        let rw = (w as usize).clamp(1, rot_w - x0);
+        let rh = (h as usize).clamp(1, rot_h - y0);
+
+        let params = params.for_context(&full);
+        let data = prepare_buffer(&full, &params);
+        let lut = params.lut()?;
+        let mut out = Vec::with_capacity(rw * rh * 4);
+        for ry in y0..y0 + rh {
+            for rx in x0..x0 + rw {
+                let (sx, sy) = rotated_to_source(rx, ry, src_w, src_h, degrees);
+                let idx = (sy * src_w + sx) * 4;
+                let [r, g, b] = grade_pixel(&data[idx..idx + 4], &params, lut.as_deref());
+                out.extend_from_slice(&[r, g, b, 1.0]);
+            }
+        }
+
+        ImageResult {
+            width: rw as u32,
+            height: rh as u32,
+            ..ImageResult::from_context(&full, out)
+        }
+        .into_response()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Split view of the cached preview: params_a left of split_fraction (0..1 of
+// the width), params_b right of it. Returns processed (display-encoded) RGBA
+// like get_region.
+#[tauri::command]
+fn render_ab(
+    state: State<AppState>,
+    params_a: ImageParams,
+    params_b: ImageParams,
+    split_fraction: f32,
+) -> Result<Response, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = guard.as_ref().ok_or(AppError::NoImageLoaded)?;
+
+    let w = preview.width as usize;
+    let split = (split_fraction.clamp(0.0, 1.0) * w as f32).round() as usize;
+    // Each side only needs its own buffer when some of it is visible
+    let side = |params: &ImageParams, visible: bool| -> Result<_, String> {
+        Ok(if visible {
+            Some((prepare_buffer(preview, params), params.lut()?))
+        } else {
+            None
+        })
+    };
+    let params_a = params_a.for_context(preview);
+    let params_b = params_b.for_context(preview);
+    let side_a = side(&params_a, split > 0)?;
+    let side_b = side(&params_b, split < w)?;
+
+    let mut out = Vec::with_capacity(preview.data.len());
+    for i in 0..preview.data.len() / 4 {
+        let (params, (data, lut)) = if i % w < split {
+            (&params_a, side_a.as_ref().unwrap())
+        } else {
+            (&params_b, side_b.as_ref().unwrap())
+        };
+        let px = &data[i * 4..i * 4 + 4];
+        let [r, g, b] = grade_pixel(px, params, lut.as_deref());
+        out.extend_from_slice(&[r, g, b, px[3]]);
+    }
+
+    ImageResult {
+        passes_skipped: (split > 0 && roi_skips_passes(preview, &params_a))
+            || (split < w && roi_skips_passes(preview, &params_b)),
+        ..ImageResult::from_context(preview, out)
+    }
+    .into_response()
+}
+
+// The cached preview with every adjustment, processed (display-encoded)
+// RGBA like render_ab. The UI shows this instead of its shader preview when
+// the params use steps the shader doesn't mirror.
+#[tauri::command]
+fn render_preview(state: State<AppState>, params: ImageParams) -> Result<Response, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = guard.as_ref().ok_or(AppError::NoImageLoaded)?;
+
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+    let mut out = Vec::with_capacity(data.len());
+    for px in data.chunks_exact(4) {
+        let [r, g, b] = grade_pixel(px, &params, lut.as_deref());
+        out.extend_from_slice(&[r, g, b, px[3]]);
+    }
+
+    ImageResult {
+        passes_skipped: roi_skips_passes(preview, &params),
+        ..ImageResult::from_context(preview, out)
+    }
+    .into_response()
+}
+
+// Whole-frame analysis and picking in frame coordinates, which an ROI
+// preview (load_raw with a crop) can't answer
+fn full_frame(preview: &PreviewContext) -> Result<&PreviewContext, AppError> {
+    match preview.roi {
+        Some(_) => Err(AppError::InvalidInput(
+            "Not available while only a crop of the image is loaded".into(),
+        )),
+        None => Ok(preview),
+    }
+}
+
+#[tauri::command]
+fn get_histogram(state: State<AppState>, params: ImageParams) -> Result<Histogram, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    let mut hist = Histogram {
+        r: vec![0; 256],
+        g: vec![0; 256],
+        b: vec![0; 256],
+    };
+
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+
+    // Data stride is 4 (RGBA)
+    for px in data.chunks_exact(4) {
+        let [r, g, b] = grade_pixel(px, &params, lut.as_deref());
+
+        hist.r[(r.clamp(0.0, 1.0) * 255.0) as usize] += 1;
+        hist.g[(g.clamp(0.0, 1.0) * 255.0) as usize] += 1;
+        hist.b[(b.clamp(0.0, 1.0) * 255.0) as usize] += 1;
+    }
+
+    Ok(hist)
+}
+
+#[tauri::command]
+fn get_waveform(state: State<AppState>, params: ImageParams) -> Result<Waveform, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    // Columns are binned down to the scope width
+    let columns = preview.width.clamp(1, WAVEFORM_COLUMNS);
+    let mut waveform = Waveform {
+        columns,
+        levels: WAVEFORM_LEVELS,
+        counts: vec![0; (columns * WAVEFORM_LEVELS) as usize],
+    };
+
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+    for (i, px) in data.chunks_exact(4).enumerate() {
+        let [r, g, b] = grade_pixel(px, &params, lut.as_deref());
+        let luma = (0.2126 * r + 0.7152 * g + 0.0722 * b).clamp(0.0, 1.0);
+
+        let x = i as u32 % preview.width;
+        let column = (x as u64 * columns as u64 / preview.width as u64) as u32;
+        let level = (luma * (WAVEFORM_LEVELS - 1) as f32).round() as u32;
+        waveform.counts[((WAVEFORM_LEVELS - 1 - level) * columns + column) as usize] += 1;
+    }
+
+    Ok(waveform)
+}
+
+#[tauri::command]
+fn get_vectorscope(state: State<AppState>, params: ImageParams) -> Result<Vectorscope, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    let mut scope = Vectorscope {
+        size: VECTORSCOPE_SIZE,
+        counts: vec![0; (VECTORSCOPE_SIZE * VECTORSCOPE_SIZE) as usize],
+    };
+    let max = (VECTORSCOPE_SIZE - 1) as f32;
+
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+    for px in data.chunks_exact(4) {
+        let [r, g, b] = grade_pixel(px, &params, lut.as_deref());
+        let (r, g, b) = (r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0));
+        // Rec.709 colour differences, each in -0.5..0.5
+        let cb = -0.1146 * r - 0.3854 * g + 0.5 * b;
+        let cr = 0.5 * r - 0.4542 * g - 0.0458 * b;
+
+        let x = ((cb + 0.5) * max).round().clamp(0.0, max) as u32;
+        let y = ((0.5 - cr) * max).round().clamp(0.0, max) as u32;
+        scope.counts[(y * VECTORSCOPE_SIZE + x) as usize] += 1;
+    }
+
+    Ok(scope)
+}
+
+// Colour readout of a swatch: `rect` is normalized over the preview as
+// displayed. Pixels outside the image after geometric corrections are skipped.
+#[tauri::command]
+fn region_stats(
+    state: State<AppState>,
+    rect: CropRect,
+    params: ImageParams,
+) -> Result<RegionStats, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    let w = preview.width;
+    let (x0, y0, rw, rh) = crop_to_pixels(Some(&rect), w, preview.height);
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+    let mut channels: [Vec<f32>; 3] = Default::default();
+    for y in y0..y0 + rh {
+        let start = (y * w + x0) as usize * 4;
+        for px in data[start..start + rw as usize * 4].chunks_exact(4) {
+            if px[3] <= 0.0 {
+                continue;
+            }
+            let [r, g, b] = grade_pixel(px, &params, lut.as_deref());
+            channels[0].push(r);
+            channels[1].push(g);
+            channels[2].push(b);
+        }
+    }
+    let pixels = channels[0].len();
+    if pixels == 0 {
+        return Err("The selected region has no image pixels".into());
+    }
+
+    let mut stats = RegionStats {
+        pixels: pixels as u32,
+        mean: [0.0; 3],
+        median: [0.0; 3],
+        min: [0.0; 3],
+        max: [0.0; 3],
+    };
+    for (c, values) in channels.iter_mut().enumerate() {
+        stats.mean[c] = values.iter().sum::<f32>() / pixels as f32;
+        stats.min[c] = values.iter().copied().fold(f32::INFINITY, f32::min);
+        stats.max[c] = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        stats.median[c] = *values.select_nth_unstable_by(pixels / 2, f32::total_cmp).1;
+    }
+    Ok(stats)
+}
+
+#[tauri::command]
+fn get_clipping_mask(state: State<AppState>, params: ImageParams) -> Result<ClippingMask, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = guard.as_ref().ok_or(AppError::NoImageLoaded)?;
+
+    let pixels = (preview.width * preview.height) as usize;
+    let mut mask = ClippingMask {
+        width: preview.width,
+        height: preview.height,
+        shadows: vec![0; pixels.div_ceil(8)],
+        highlights: vec![0; pixels.div_ceil(8)],
+    };
+
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+    for (i, px) in data.chunks_exact(4).enumerate() {
+        let [r, g, b] = grade_pixel(px, &params, lut.as_deref());
+        let bit = 1 << (i % 8);
+
+        if r <= 0.0 || g <= 0.0 || b <= 0.0 {
+            mask.shadows[i / 8] |= bit;
+        }
+        if r >= 1.0 || g >= 1.0 || b >= 1.0 {
+            mask.highlights[i / 8] |= bit;
+        }
+    }
+
+    Ok(mask)
+}
+
+// Marks preview pixels whose local contrast (Sobel gradient of the
+// sRGB-encoded luminance, scaled so a hard black/white edge is about 1)
+// exceeds `threshold`. Uses the unedited preview: focus doesn't depend on
+// the grading, and border pixels are never marked.
+#[tauri::command]
+fn get_focus_peaking(state: State<AppState>, threshold: f32) -> Result<PeakingMask, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = guard.as_ref().ok_or(AppError::NoImageLoaded)?;
+
+    let w = preview.width as usize;
+    let h = preview.height as usize;
+    let luma: Vec<f32> = preview
+        .data
+        .chunks_exact(4)
+        .map(|px| srgb_encode((0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2]).max(0.0)))
+        .collect();
+    let mut mask = PeakingMask {
+        width: preview.width,
+        height: preview.height,
+        edges: vec![0; (w * h).div_ceil(8)],
+    };
+
+    let threshold2 = threshold * threshold;
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            let at = |dx: usize, dy: usize| luma[(y + dy - 1) * w + x + dx - 1];
+            let gx =
+                (at(2, 0) + 2.0 * at(2, 1) + at(2, 2)) - (at(0, 0) + 2.0 * at(0, 1) + at(0, 2));
+            let gy =
+                (at(0, 2) + 2.0 * at(1, 2) + at(2, 2)) - (at(0, 0) + 2.0 * at(1, 0) + at(2, 0));
+            if (gx * gx + gy * gy) / 16.0 > threshold2 {
+                let i = y * w + x;
+                mask.edges[i / 8] |= 1 << (i % 8);
+            }
+        }
+    }
+
+    Ok(mask)
+}
+
+// Processed (display-encoded) RGB at each preview coordinate, for the colour
+// sampler. All points come in one call to keep IPC traffic down while dragging.
+#[tauri::command]
+fn sample_pixels(
+    state: State<AppState>,
+    coords: Vec<(u32, u32)>,
+    params: ImageParams,
+) -> Result<Vec<[f32; 3]>, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    if let Some((x, y)) = coords
+        .iter()
+        .find(|(x, y)| *x >= preview.width || *y >= preview.height)
+    {
+        return Err(format!(
+            "Pixel ({}, {}) is outside the {}x{} preview",
+            x, y, preview.width, preview.height
+        ));
+    }
+
+    let params = params.for_context(preview);
+    let data = prepare_buffer(preview, &params);
+    let lut = params.lut()?;
+    Ok(coords
+        .iter()
+        .map(|&(x, y)| {
+            let idx = (y * preview.width + x) as usize * 4;
+            grade_pixel(&data[idx..idx + 4], &params, lut.as_deref())
+        })
+        .collect())
+}
+
+// Suggests exposure, contrast and levels from the preview's luminance:
+// exposure puts the median near middle grey, then the levels place the
+// 0.5th / 99.5th percentiles at black / white. Values are clamped to the
+// slider ranges; other params are left neutral.
+#[tauri::command]
+fn auto_tone(state: State<AppState>) -> Result<ImageParams, String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    let mut luma = filters::luminance(&preview.data);
+    luma.retain(|l| l.is_finite());
+    if luma.is_empty() {
+        return Err("Preview is empty".into());
+    }
+    luma.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f32| luma[((luma.len() - 1) as f32 * p).round() as usize].max(0.0);
+
+    let mut params = ImageParams::default();
+
+    let median = percentile(0.5);
+    if median > 0.0 {
+        params.exposure = (0.18 / median).log2().clamp(-3.0, 3.0);
+    }
+    let mult = 2.0_f32.powf(params.exposure);
+
+    // Contrast from the spread of the middle half, measured in gamma space.
+    // The factor is 1 + contrast, as in step 3 of apply_adjustments.
+    let q1 = (percentile(0.25) * mult).min(1.0).powf(1.0 / 2.2);
+    let q3 = (percentile(0.75) * mult).min(1.0).powf(1.0 / 2.2);
+    if q3 - q1 > 1e-3 {
+        params.contrast = (0.3 / (q3 - q1) - 1.0).clamp(-0.5, 0.5);
+    }
+    let contrast_factor = 1.0 + params.contrast;
+    let tone = |v: f32| (v * mult - 0.5) * contrast_factor + 0.5;
+
+    // Levels: black point = blacks * 0.2, white point = 1 + whites * 0.2
+    params.blacks = (tone(percentile(0.005)) / 0.2).clamp(-1.0, 1.0);
+    params.whites = ((tone(percentile(0.995)) - 1.0) / 0.2).clamp(-1.0, 1.0);
+
+    Ok(params)
+}
+
+// White balance eyedropper: the (temperature, tint) that makes the preview
+// pixel at (x, y) neutral. Inverts the WB step of apply_processing, which
+// only holds for a preview decoded with the daylight multipliers.
+#[tauri::command]
+fn wb_from_pixel(state: State<AppState>, x: u32, y: u32) -> Result<(f32, f32), String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+    if preview.monochrome_sensor {
+        return Err(
+            AppError::InvalidInput("Monochrome images have no white balance".into()).into(),
+        );
+    }
+    if !preview.daylight_wb {
+        return Err(AppError::InvalidInput(
+            "The preview was decoded with the camera's white balance; reload it in Custom mode"
+                .into(),
+        )
+        .into());
+    }
+    if x >= preview.width || y >= preview.height {
+        return Err(format!(
+            "Pixel ({}, {}) is outside the {}x{} preview",
+            x, y, preview.width, preview.height
+        ));
+    }
+
+    // Average a 3x3 neighbourhood to reduce noise
+    let mut sum = [0.0_f32; 3];
+    let mut count = 0.0;
+    for sy in y.saturating_sub(1)..=(y + 1).min(preview.height - 1) {
+        for sx in x.saturating_sub(1)..=(x + 1).min(preview.width - 1) {
+            let idx = (sy * preview.width + sx) as usize * 4;
+            sum[0] += preview.data[idx];
+            sum[1] += preview.data[idx + 1];
+            sum[2] += preview.data[idx + 2];
+            count += 1.0;
+        }
+    }
+    let (r, g, b) = (sum[0] / count, sum[1] / count, sum[2] / count);
+    if r <= 0.0 || g <= 0.0 || b <= 0.0 {
+        return Err("Selected pixel is too dark to sample".into());
+    }
+
+    // Temperature that balances red against blue, then green is pulled to
+    // the balanced red/blue level
+    let (min, max) = wb::TEMPERATURE_RANGE;
+    let temperature = wb::temperature_for_ratio(b / r, min, max);
+    let [wb_r, _, wb_b] = wb::temperature_gains(temperature);
+    let target = (r * wb_r).max(b * wb_b);
+    let tint = ((target / g - 1.0) * 100.0).clamp(wb::TINT_RANGE.0, wb::TINT_RANGE.1);
+
+    Ok((temperature, tint))
+}
+
+// Film base colour for film_negative: mean linear RGB of the unedited
+// preview over `rect` (normalized), which should cover unexposed film such
+// as the gap between frames
+#[tauri::command]
+fn sample_film_base(state: State<AppState>, rect: CropRect) -> Result<[f32; 3], String> {
+    let guard = state.preview_context.lock().unwrap();
+    let preview = full_frame(guard.as_ref().ok_or(AppError::NoImageLoaded)?)?;
+
+    let w = preview.width;
+    let (x0, y0, rw, rh) = crop_to_pixels(Some(&rect), w, preview.height);
+    let mut sum = [0.0_f64; 3];
+    for y in y0..y0 + rh {
+        let start = (y * w + x0) as usize * 4;
+        for px in preview.data[start..start + rw as usize * 4].chunks_exact(4) {
+            sum[0] += px[0] as f64;
+            sum[1] += px[1] as f64;
+            sum[2] += px[2] as f64;
+        }
+    }
+    let count = (rw * rh) as f64;
+    let base = sum.map(|v| (v / count) as f32);
+    if base.iter().any(|&v| v <= 0.0) {
+        return Err("Selected area is too dark to be film base".into());
+    }
+    Ok(base)
+}
+
+// (temperature, tint) for a named white balance preset, for Custom mode
+#[tauri::command]
+fn wb_preset(name: &str) -> Result<(f32, f32), String> {
+    wb::preset(name).ok_or(format!("Unknown white balance preset: {}", name))
+}
+
+// PNG iTXt keyword holding the export's ImageParams
+const PARAMS_TEXT_KEY: &str = "ImageParams";
+
+fn params_json(params: &ImageParams) -> Result<String, String> {
+    serde_json::to_string_pretty(params).map_err(|e| e.to_string())
+}
+
+// Params as embedded in an export, plus the output space it was rendered in
+// (an extra key that loading the params ignores)
+fn embedded_params_json(params: &ImageParams, space: OutputSpace) -> Result<String, AppError> {
+    serde_json::to_string_pretty(&embedded_params_value(params, space)?).map_err(encode_error)
+}
+
+fn embedded_params_value(
+    params: &ImageParams,
+    space: OutputSpace,
+) -> Result<serde_json::Value, AppError> {
+    let mut value = serde_json::to_value(params).map_err(encode_error)?;
+    value["output_space"] = serde_json::json!(space);
+    Ok(value)
+}
+
+// ImageDescription of export_linear_tiff: the embedded params, so
+// read_embedded_params restores them, plus a "transfer" key. No standard
+// TIFF tag says "linear" without an ICC profile, so it's recorded there for
+// tools (and people) that look.
+fn linear_tiff_description(params: &ImageParams, space: OutputSpace) -> Result<String, AppError> {
+    let mut value = embedded_params_value(params, space)?;
+    value["transfer"] = serde_json::json!("linear");
+    serde_json::to_string_pretty(&value).map_err(encode_error)
+}
+
+#[tauri::command]
+fn default_params() -> ImageParams {
+    ImageParams::default()
+}
+
+#[tauri::command]
+fn save_params(path: &str, params: ImageParams) -> Result<(), String> {
+    let json_val = params_json(&params)?;
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    file.write_all(json_val.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+fn load_params(path: &str) -> Result<ImageParams, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let params: ImageParams = serde_json::from_reader(file).map_err(|e| e.to_string())?;
+    Ok(params)
+}
+
+// Approximate import of Lightroom / Camera Raw edits from an XMP sidecar.
+// The 2012 process sliders (-100..100) are scaled onto ours and clamped to
+// the slider ranges; anything without a counterpart is ignored.
+#[tauri::command]
+fn load_xmp(path: &str) -> Result<ImageParams, String> {
+    let xml = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Ok(params_from_xmp(&xml))
+}
+
+fn params_from_xmp(xml: &str) -> ImageParams {
+    let mut params = ImageParams::default();
+    let number = |field: &str| xmp::crs_number(xml, field);
+
+    if let Some(v) = number("Exposure2012") {
+        params.exposure = v.clamp(-3.0, 3.0);
+    }
+    if let Some(v) = number("Contrast2012") {
+        params.contrast = (v / 200.0).clamp(-0.5, 0.5);
+    }
+    if let Some(v) = number("Highlights2012") {
+        params.highlights = (v / 100.0).clamp(-1.0, 1.0);
+    }
+    if let Some(v) = number("Shadows2012") {
+        params.shadows = (v / 100.0).clamp(-1.0, 1.0);
+    }
+    if let Some(v) = number("Whites2012") {
+        params.whites = (v / 100.0).clamp(-1.0, 1.0);
+    }
+    if let Some(v) = number("Blacks2012") {
+        params.blacks = (v / 100.0).clamp(-1.0, 1.0);
+    }
+    if let Some(v) = number("Saturation") {
+        params.saturation = (v / 100.0).clamp(-1.0, 1.0);
+    }
+
+    // Temperature/Tint only replace the camera's balance when the edit
+    // changed it; "As Shot" keeps ours as shot too
+    let white_balance = xmp::crs_value(xml, "WhiteBalance").unwrap_or("As Shot");
+    if white_balance != "As Shot" {
+        if let Some(v) = number("Temperature") {
+            params.wb_mode = WbMode::Custom;
+            let (min, max) = wb::TEMPERATURE_RANGE;
+            params.temperature = v.clamp(min, max);
+            // Lightroom's tint runs -150..150 with positive towards magenta;
+            // ours runs -50..50 as a green gain
+            let (min, max) = wb::TINT_RANGE;
+            params.tint = number("Tint").map_or(0.0, |t| (-t / 3.0).clamp(min, max));
+        }
+    }
+
+    params
+}
+
+// Saved editing session: the RAW the edits belong to plus the edits
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Session {
+    source_raw: String,
+    params: ImageParams,
+}
+
+#[tauri::command]
+fn save_session(path: &str, source_raw: &str, params: ImageParams) -> Result<(), String> {
+    let session = Session {
+        source_raw: source_raw.to_string(),
+        params,
+    };
+    let json_val = serde_json::to_string_pretty(&session).map_err(|e| e.to_string())?;
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    file.write_all(json_val.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+fn load_session(path: &str) -> Result<Session, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let session: Session = serde_json::from_reader(file).map_err(|e| e.to_string())?;
+    if !Path::new(&session.source_raw).is_file() {
+        return Err(format!(
+            "The RAW file for this session could not be found: {}",
+            session.source_raw
+        ));
+    }
+    Ok(session)
+}
+
+// Named look kept in the app's presets directory
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Preset {
+    name: String,
+    // Optional path of an image showing the look
+    #[serde(default)]
+    thumbnail: Option<String>,
+    params: ImageParams,
+}
+
+#[derive(Serialize)]
+struct PresetInfo {
+    name: String,
+    thumbnail: Option<String>,
+}
+
+fn presets_dir(app: &AppHandle) -> Result<std::path::PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("presets");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+// One JSON file per preset, named after it. Names that could escape the
+// presets directory are refused.
+fn preset_path(app: &AppHandle, name: &str) -> Result<std::path::PathBuf, String> {
+    let name = name.trim();
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.contains(|c: char| matches!(c, '/' | '\\' | ':') || c.is_control())
+    {
+        return Err(format!("Invalid preset name: {:?}", name));
+    }
+    Ok(presets_dir(app)?.join(format!("{}.json", name)))
+}
+
+fn read_preset(path: &Path) -> Result<Preset, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    serde_json::from_reader(file).map_err(|e| e.to_string())
+}
+
+// Saves (or overwrites) a named preset
+#[tauri::command]
+fn save_preset(
+    app: AppHandle,
+    name: &str,
+    params: ImageParams,
+    thumbnail: Option<String>,
+) -> Result<(), String> {
+    let preset = Preset {
+        name: name.trim().to_string(),
+        thumbnail,
+        params,
+    };
+    let json_val = serde_json::to_string_pretty(&preset).map_err(|e| e.to_string())?;
+    let mut file = File::create(preset_path(&app, name)?).map_err(|e| e.to_string())?;
+    file.write_all(json_val.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Presets sorted by name. Unreadable files are skipped.
+#[tauri::command]
+fn list_presets(app: AppHandle) -> Result<Vec<PresetInfo>, String> {
+    let mut presets: Vec<PresetInfo> = std::fs::read_dir(presets_dir(&app)?)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .filter_map(|path| read_preset(&path).ok())
+        .map(|preset| PresetInfo {
+            name: preset.name,
+            thumbnail: preset.thumbnail,
+        })
+        .collect();
+    presets.sort_by_key(|p| p.name.to_lowercase());
+    Ok(presets)
+}
+
+#[tauri::command]
+fn apply_preset(app: AppHandle, name: &str) -> Result<ImageParams, String> {
+    let path = preset_path(&app, name)?;
+    if !path.is_file() {
+        return Err(format!("No preset named {:?}", name));
+    }
+    Ok(read_preset(&path)?.params)
+}
+
+#[tauri::command]
+fn delete_preset(app: AppHandle, name: &str) -> Result<(), String> {
+    std::fs::remove_file(preset_path(&app, name)?).map_err(|e| e.to_string())
+}
+
+// A subset of ImageParams, keyed by field name as they serialize, for
+// copying some adjustments from one image to others
+#[derive(serde::Deserialize, Serialize)]
+#[serde(transparent)]
+struct PartialParams(serde_json::Map<String, serde_json::Value>);
+
+// Names copy_settings accepts besides single fields. Every ImageParams field
+// belongs to exactly one group.
+const SETTING_GROUPS: &[(&str, &[&str])] = &[
+    ("white_balance", &["wb_mode", "temperature", "tint"]),
+    (
+        "tone",
+        &[
+            "exposure",
+            "contrast",
+            "highlights",
+            "shadows",
+            "whites",
+            "blacks",
+            "shadow_range",
+            "highlight_range",
+            "curve",
+            "tone_mapping",
+            "transfer_curve",
+        ],
+    ),
+    (
+        "color",
+        &[
+            "saturation",
+            "vibrance",
+            "hsl",
+            "red_gain",
+            "green_gain",
+            "blue_gain",
+            "gamut_compress",
+            "adjustment_space",
+            "apply_lut",
+        ],
+    ),
+    (
+        "split_toning",
+        &[
+            "shadow_hue",
+            "shadow_sat",
+            "highlight_hue",
+            "highlight_sat",
+            "balance",
+        ],
+    ),
+    (
+        "black_white",
+        &["monochrome", "bw_red", "bw_green", "bw_blue"],
+    ),
+    (
+        "detail",
+        &[
+            "clarity",
+            "sharpen_amount",
+            "sharpen_radius",
+            "noise_reduction",
+            "grain_amount",
+            "grain_size",
+        ],
+    ),
+    (
+        "lens",
+        &[
+            "lens_correction",
+            "profile_vignetting",
+            "vignette_correction",
+            "ca_red_scale",
+            "ca_blue_scale",
+        ],
+    ),
+    (
+        "geometry",
+        &[
+            "crop",
+            "rotation",
+            "auto_orient",
+            "straighten_angle",
+            "perspective_vertical",
+            "perspective_horizontal",
+        ],
+    ),
+    ("local", &["gradients", "radial_filters", "layers"]),
+    ("film", &["film_negative", "film_base"]),
+];
+
+fn params_object(
+    params: &ImageParams,
+) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    match serde_json::to_value(params).map_err(|e| e.to_string())? {
+        serde_json::Value::Object(map) => Ok(map),
+        _ => Err("ImageParams did not serialize to an object".into()),
+    }
+}
+
+// Picks the given fields (or groups, see SETTING_GROUPS) out of params
+#[tauri::command]
+fn copy_settings(params: ImageParams, fields: Vec<String>) -> Result<PartialParams, String> {
+    let all = params_object(&params)?;
+    let mut patch = serde_json::Map::new();
+    for field in &fields {
+        let names = SETTING_GROUPS
+            .iter()
+            .find(|(group, _)| *group == field.as_str())
+            .map_or_else(|| vec![field.as_str()], |(_, names)| names.to_vec());
+        for name in names {
+            let value = all.get(name).ok_or(format!("Unknown setting: {}", name))?;
+            patch.insert(name.to_string(), value.clone());
+        }
+    }
+    Ok(PartialParams(patch))
+}
+
+// base with the copied fields replaced
+#[tauri::command]
+fn paste_settings(base: ImageParams, patch: PartialParams) -> Result<ImageParams, String> {
+    let mut merged = params_object(&base)?;
+    merged.extend(patch.0);
+    serde_json::from_value(serde_json::Value::Object(merged)).map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct LutHeader {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+}
+
+// The apply_lut table for the preview to mirror: LutHeader, then size^3 RGB
+// entries with red varying fastest, then green, then blue
+#[tauri::command]
+fn get_lut(path: &str) -> Result<Response, String> {
+    let lut = lut::load(path)?;
+    let (domain_min, domain_max) = lut.domain();
+    let header = LutHeader {
+        size: lut.size(),
+        domain_min,
+        domain_max,
+    };
+    let table: Vec<f32> = lut.table().iter().flatten().copied().collect();
+    binary_response(&header, &table)
+}
+
+// Recovers the params embedded by an export (PNG iTXt or TIFF ImageDescription)
+#[tauri::command]
+fn read_embedded_params(path: &str) -> Result<ImageParams, String> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let file = File::open(path).map_err(|e| e.to_string())?;
+
+    let json = match ext.as_str() {
+        "png" => {
+            let reader = png::Decoder::new(file)
+                .read_info()
+                .map_err(|e| e.to_string())?;
+            reader
+                .info()
+                .utf8_text
+                .iter()
+                .find(|chunk| chunk.keyword == PARAMS_TEXT_KEY)
+                .map(|chunk| chunk.get_text())
+                .transpose()
+                .map_err(|e| e.to_string())?
+        }
+        "tif" | "tiff" => {
+            let mut decoder = TiffDecoder::new(file).map_err(|e| e.to_string())?;
+            decoder.get_tag_ascii_string(Tag::ImageDescription).ok()
+        }
+        _ => return Err(format!("No embedded params in .{} files", ext)),
+    };
+
+    let json = json.ok_or("File has no embedded params")?;
+    serde_json::from_str(&json).map_err(|e| e.to_string())
+}
+
+fn lens_profiles_path(app: &AppHandle) -> Result<std::path::PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join("lens_profiles.json"))
+}
+
+// Replaces the distortion profiles used by lens_correction and keeps them
+// for later sessions
+#[tauri::command]
+fn set_lens_profiles(app: AppHandle, profiles: Vec<lens::LensProfile>) -> Result<(), String> {
+    lens::validate(&profiles)?;
+    let json_val = serde_json::to_string_pretty(&profiles).map_err(|e| e.to_string())?;
+    std::fs::write(lens_profiles_path(&app)?, json_val).map_err(|e| e.to_string())?;
+    lens::set_profiles(profiles);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_lens_profiles(app: AppHandle) -> Result<Vec<lens::LensProfile>, String> {
+    let path = lens_profiles_path(&app)?;
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn push_params_history(state: State<AppState>, params: ImageParams) {
+    state.params_history.lock().unwrap().push(params);
+}
+
+#[tauri::command]
+fn undo_params(state: State<AppState>) -> Option<ImageParams> {
+    state.params_history.lock().unwrap().undo()
+}
+
+#[tauri::command]
+fn redo_params(state: State<AppState>) -> Option<ImageParams> {
+    state.params_history.lock().unwrap().redo()
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        // Profiles saved by set_lens_profiles; without them (or if the file is
+        // unreadable) lens_correction does nothing
+        .setup(|app| {
+            if let Ok(profiles) = get_lens_profiles(app.handle().clone()) {
+                lens::set_profiles(profiles);
+            }
+            Ok(())
+        })
+        .manage(AppState {
+            preview_context: Mutex::new(None),
+            current_path: Mutex::new(None),
+            merge_paths: Mutex::new(Vec::new()),
+            full_res_cache: Mutex::new(None),
+            params_history: Mutex::new(ParamsHistory::default()),
+            load_generation: AtomicU64::new(0),
+            decode_lock: Mutex::new(()),
+            export_generation: AtomicU64::new(0),
+            export_cancelled: AtomicU64::new(0),
+        })
+        .invoke_handler(tauri::generate_handler![
+            load_raw,
+            load_raw_merge,
+            load_thumbnail,
+            generate_thumbnails,
+            resample_preview,
+            probe_file,
+            supported_extensions,
+            export_image,
+            export_batch,
+            export_preview,
+            export_linear_tiff,
+            cancel_export,
+            get_region,
+            render_ab,
+            render_preview,
+            get_histogram,
+            get_waveform,
+            get_vectorscope,
+            region_stats,
+            get_clipping_mask,
+            get_focus_peaking,
+            auto_tone,
+            sample_pixels,
+            wb_from_pixel,
+            wb_preset,
+            sample_film_base,
+            default_params,
+            save_params,
+            load_params,
+            load_xmp,
+            read_embedded_params,
+            get_lut,
+            set_lens_profiles,
+            get_lens_profiles,
+            save_session,
+            load_session,
+            save_preset,
+            list_presets,
+            apply_preset,
+            delete_preset,
+            copy_settings,
+            paste_settings,
+            push_params_history,
+            undo_params,
+            redo_params
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    fn process(rgb: [f32; 3], params: &ImageParams) -> [f32; 3] {
+        let (r, g, b) = apply_processing_in(rgb[0], rgb[1], rgb[2], params, OutputSpace::Srgb);
+        [r, g, b]
+    }
+
+    fn assert_rgb(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.into_iter().zip(expected) {
+            assert_close(a, e);
+        }
+    }
+
+    #[test]
+    fn neutral_params_only_encode() {
+        let params = ImageParams::default();
+        assert_rgb(process([0.18; 3], &params), [0.461356; 3]);
+        assert_rgb(
+            process([0.2, 0.5, 0.1], &params),
+            [0.484529, 0.735357, 0.349190],
+        );
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let params = ImageParams {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        // 0.36 linear
+        assert_rgb(process([0.18; 3], &params), [0.634253; 3]);
+    }
+
+    #[test]
+    fn contrast_pivots_on_half() {
+        let params = ImageParams {
+            contrast: 0.5,
+            ..Default::default()
+        };
+        // (0.18 - 0.5) * 1.5 + 0.5 = 0.02 linear
+        assert_rgb(process([0.18; 3], &params), [0.151704; 3]);
+    }
+
+    #[test]
+    fn saturation_scales_around_luminance() {
+        let params = ImageParams {
+            saturation: 1.0,
+            ..Default::default()
+        };
+        // Luminance 0.3643, distances from it doubled
+        assert_rgb(
+            process([0.3, 0.4, 0.2], &params),
+            [0.522744, 0.691301, 0.208146],
+        );
+        // Grey has no chroma to scale
+        assert_rgb(process([0.18; 3], &params), [0.461356; 3]);
+    }
+
+    fn luminance(rgb: [f32; 3]) -> f32 {
+        0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+    }
+
+    #[test]
+    fn compress_gamut_keeps_luminance_and_hue() {
+        for input in [[1.5, 0.5, 0.2], [0.8, 0.3, -0.2], [-0.1, 0.2, 1.4]] {
+            let mut rgb = input;
+            compress_gamut(&mut rgb);
+            assert!(
+                rgb.iter().all(|c| (0.0..=1.0 + 1e-6).contains(c)),
+                "{rgb:?}"
+            );
+
+            let l = luminance(input);
+            assert_close(luminance(rgb), l);
+            // Same direction away from grey, only shorter
+            let t = (rgb[0] - l) / (input[0] - l);
+            assert!(t > 0.0 && t < 1.0);
+            for (out, c) in rgb.into_iter().zip(input) {
+                assert_close(out - l, (c - l) * t);
+            }
+        }
+    }
+
+    #[test]
+    fn compress_gamut_leaves_in_gamut_colours() {
+        let mut rgb = [0.7, 0.4, 0.1];
+        compress_gamut(&mut rgb);
+        assert_eq!(rgb, [0.7, 0.4, 0.1]);
+
+        let mut bright = [3.0, 2.0, 0.5];
+        compress_gamut(&mut bright);
+        assert_eq!(bright, [1.0; 3]);
+    }
+
+    // Largest difference, in 8-bit code values, between the mean of an 8x8
+    // tile of a quantized slow ramp (0.2..0.3 over 512 px) and the ramp itself
+    fn ramp_tile_error(dither: bool) -> f32 {
+        const WIDTH: u32 = 512;
+        let ramp = |x: u32| 0.2 + 0.1 * x as f32 / WIDTH as f32;
+        (0..WIDTH)
+            .step_by(8)
+            .map(|tx| {
+                let (mut quantized, mut input) = (0.0, 0.0);
+                for y in 0..8 {
+                    for x in tx..tx + 8 {
+                        quantized += quantize_u8(ramp(x), x, y, dither) as f32;
+                        input += ramp(x) * 255.0;
+                    }
+                }
+                (quantized - input).abs() / 64.0
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn dithered_ramp_has_no_bands() {
+        assert!(ramp_tile_error(true) < 0.1);
+        // Plain rounding steps every ~20 px, so whole tiles land on one code
+        assert!(ramp_tile_error(false) > 0.25);
+    }
+
+    #[test]
+    fn quantize_rounds_to_nearest() {
+        assert_eq!(quantize_u8(0.0, 0, 0, false), 0);
+        assert_eq!(quantize_u8(0.5, 0, 0, false), 128);
+        assert_eq!(quantize_u8(0.998, 0, 0, false), 254);
+        assert_eq!(quantize_u8(0.999, 0, 0, false), 255);
+        assert_eq!(quantize_u8(1.0, 0, 0, false), 255);
+        assert_eq!(quantize_u8(1.5, 0, 0, false), 255);
+        assert_eq!(quantize_u8(-0.5, 0, 0, false), 0);
+        assert!((0..=255).all(|k| quantize_u8(k as f32 / 255.0, 0, 0, false) == k as u8));
+
+        assert_eq!(quantize_u16(0.0), 0);
+        assert_eq!(quantize_u16(0.5), 32768);
+        assert_eq!(quantize_u16(0.999), 65469);
+        assert_eq!(quantize_u16(1.0), 65535);
+        assert_eq!(quantize_u16(1.5), 65535);
+        assert_eq!(quantize_u16(-0.5), 0);
+    }
+
+    #[test]
+    fn srgb_toe_is_linear_near_black() {
+        for v in [0.0001, 0.001, 0.003] {
+            assert_close(srgb_encode(v), 12.92 * v);
+            // A plain 2.2 power lifts near-black far more
+            assert!(srgb_encode(v) < v.powf(1.0 / 2.2));
+        }
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert_close(srgb_encode(1.0), 1.0);
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=1000 {
+            let v = i as f32 / 1000.0;
+            assert_close(srgb_decode(srgb_encode(v)), v);
+            assert_close(srgb_encode(srgb_decode(v)), v);
+        }
+    }
+
+    #[test]
+    fn unusable_as_shot_coefficients_are_estimated() {
+        let daylight = [2.0, 1.0, 1.5, 0.0];
+        for cam_mul in [[0.0; 4], [2.1, 1.0, 0.0, 1.0], [-1.0, 1.0, 1.4, 1.0]] {
+            assert!(!valid_wb_coeffs(&cam_mul));
+            assert!(wb_estimated(WbMode::AsShot, false, &cam_mul));
+            assert!(as_shot_temperature(&cam_mul, &daylight).is_none());
+            // Not a fallback when the as-shot values aren't used
+            assert!(!wb_estimated(WbMode::Custom, false, &cam_mul));
+            assert!(!wb_estimated(WbMode::AsShot, true, &cam_mul));
+        }
+
+        // cam_mul[3] may legitimately be 0 (filled from green by libraw)
+        let cam_mul = [2.1, 1.0, 1.4, 0.0];
+        assert!(!wb_estimated(WbMode::AsShot, false, &cam_mul));
+        assert!(as_shot_temperature(&cam_mul, &daylight).is_some());
+    }
+
+    #[test]
+    fn check_buffer_rejects_corrupt_sizes() {
+        // 4x3 RGB at 16 bits is 72 bytes
+        assert!(check_buffer(4, 3, 3, 16, 72).is_ok());
+        assert!(check_buffer(4, 3, 3, 16, 100).is_ok());
+        assert!(check_buffer(4, 3, 1, 8, 12).is_ok());
+
+        assert!(matches!(
+            check_buffer(4, 3, 3, 16, 71),
+            Err(AppError::Decode(_))
+        ));
+        assert!(check_buffer(4, 3, 3, 8, 35).is_err());
+        assert!(check_buffer(usize::MAX, 2, 3, 16, usize::MAX).is_err());
+        assert!(check_buffer(0, 3, 3, 16, 72).is_err());
+        assert!(check_buffer(4, 0, 3, 16, 72).is_err());
+        assert!(check_buffer(4, 3, 0, 16, 72).is_err());
+    }
+
+    #[test]
+    fn baseline_exposure_ignores_sentinel_and_outliers() {
+        assert_eq!(baseline_exposure_ev(0.0), 0.0);
+        assert_eq!(baseline_exposure_ev(0.35), 0.35);
+        assert_eq!(baseline_exposure_ev(-1.5), -1.5);
+        assert_eq!(baseline_exposure_ev(10.0), 10.0);
+        assert_eq!(baseline_exposure_ev(-10.0), -10.0);
+
+        assert_eq!(baseline_exposure_ev(-999.0), 0.0);
+        assert_eq!(baseline_exposure_ev(10.5), 0.0);
+        assert_eq!(baseline_exposure_ev(-10.5), 0.0);
+        assert_eq!(baseline_exposure_ev(f32::NAN), 0.0);
+        assert_eq!(baseline_exposure_ev(f32::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn lowest_channel_white_level_sets_saturation() {
+        // 14-bit container (16383) whose channels clip well below full range
+        assert_eq!(
+            saturation_level([15000, 15600, 15200, 15600], 512, 16383),
+            Some(15000)
+        );
+        // Unset channels are ignored
+        assert_eq!(saturation_level([0, 3900, 0, 4000], 256, 4095), Some(3900));
+
+        assert_eq!(saturation_level([0; 4], 512, 16383), None);
+        assert_eq!(saturation_level([16383; 4], 512, 16383), None);
+        assert_eq!(saturation_level([400, 4000, 4000, 4000], 512, 16383), None);
+    }
+
+    #[test]
+    fn white_level_decodes_to_one() {
+        // libraw scales the clip level (user_sat - black) to the top 16-bit code
+        let raw = bytes16(&[65535, 65535, 65535]);
+        let layout = RawLayout {
+            width: 1,
+            height: 1,
+            channels: 3,
+            bits: 16,
+        };
+        assert_eq!(pack_rgba(&raw, &layout, 1, 1, 1.0)[..3], [1.0; 3]);
+        // Baseline exposure is the only gain on top
+        assert_eq!(pack_rgba(&raw, &layout, 1, 1, 0.5)[..3], [0.5; 3]);
+    }
+
+    fn thumbnail_pixels(workers: usize) -> Vec<Option<Vec<u8>>> {
+        // Gradients of different sizes, with gaps for files without a preview
+        let embedded = (0..9u32)
+            .map(|i| {
+                let (w, h) = (40 + i * 7, 30 + i * 5);
+                let bytes = (0..w * h * 3).map(|k| (k * (i + 3) % 251) as u8).collect();
+                (i % 4 != 2).then(|| (EmbeddedThumb::Bitmap(w, h, bytes), (i % 4) as u16 * 90))
+            })
+            .collect();
+        decode_embedded(embedded, 16, workers)
+            .into_iter()
+            .map(|thumb| thumb.map(|rgb| rgb.unwrap().into_raw()))
+            .collect()
+    }
+
+    #[test]
+    fn thumbnails_match_whatever_the_worker_count() {
+        let serial = thumbnail_pixels(1);
+        assert_eq!(serial.iter().filter(|t| t.is_none()).count(), 2);
+        for workers in [2, 3, 8, 16] {
+            assert!(thumbnail_pixels(workers) == serial);
+        }
+    }
+
+    fn bytes16(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn pack_rgba_reads_full_range_16_bit() {
+        // Float DNGs reach us already converted to 16-bit by libraw: codes
+        // 0, mid and 65535 become exactly 0.0, code / 65535 and 1.0
+        let raw = bytes16(&[0, 32768, 65535, 65535, 0, 1]);
+        let layout = RawLayout {
+            width: 2,
+            height: 1,
+            channels: 3,
+            bits: 16,
+        };
+        let out = pack_rgba(&raw, &layout, 1, 1, 1.0);
+        assert_eq!(out.len(), 8);
+        assert_eq!(out[0], 0.0);
+        assert_eq!(out[1], 32768.0 / 65535.0);
+        assert_eq!(out[2], 1.0);
+        assert_eq!(out[4], 1.0);
+        assert_eq!(out[5], 0.0);
+        assert_eq!(out[6], 1.0 / 65535.0);
+        assert_eq!((out[3], out[7]), (1.0, 1.0));
+    }
+
+    #[test]
+    fn pack_rgba_keeps_linear_rgb() {
+        // Linear DNG: already RGB, from libraw as 16-bit. 4x2 pixels in
+        // steps of 13107 = 65535 / 5, so each code is a multiple of 0.2.
+        let fifths: [[u16; 3]; 8] = [
+            [1, 2, 3],
+            [2, 3, 4],
+            [3, 4, 5],
+            [0, 1, 2],
+            [4, 3, 2],
+            [5, 4, 5],
+            [1, 1, 1],
+            [2, 2, 2],
+        ];
+        let codes: Vec<u16> = fifths.iter().flatten().map(|&v| v * 13107).collect();
+        let raw = bytes16(&codes);
+        let layout = RawLayout {
+            width: 4,
+            height: 2,
+            channels: 3,
+            bits: 16,
+        };
+
+        // Unchanged (no tone curve or matrix) apart from the gain
+        let out = pack_rgba(&raw, &layout, 1, 1, 2.0);
+        assert_eq!(out.len(), 4 * 2 * 4);
+        assert_rgb([out[0], out[1], out[2]], [0.4, 0.8, 1.2]);
+        assert_rgb([out[20], out[21], out[22]], [2.0, 1.6, 2.0]);
+        assert_eq!(out[23], 1.0);
+
+        // Every second pixel
+        let out = pack_rgba(&raw, &layout, 2, 1, 1.0);
+        assert_eq!(out.len(), 2 * 4);
+        assert_rgb([out[4], out[5], out[6]], [0.6, 0.8, 1.0]);
+
+        // 2x2 block means (X-Trans previews): pixels 0, 1, 4, 5
+        let out = pack_rgba(&raw, &layout, 2, 2, 1.0);
+        assert_rgb([out[0], out[1], out[2]], [0.6, 0.6, 0.7]);
+    }
+
+    #[test]
+    fn pack_rgba_repeats_single_channel_as_grey() {
+        let raw = bytes16(&[0, 16384, 65535]);
+        let layout = RawLayout {
+            width: 3,
+            height: 1,
+            channels: 1,
+            bits: 16,
+        };
+        let out = pack_rgba(&raw, &layout, 1, 1, 1.0);
+        for (px, v) in out.chunks_exact(4).zip([0.0, 16384.0 / 65535.0, 1.0]) {
+            assert_rgb([px[0], px[1], px[2]], [v; 3]);
+        }
+    }
+
+    // 6x4 RGGB mosaic inside a buffer with a 2-pixel left margin
+    fn rggb(g1: u16, g2: u16) -> Vec<u16> {
+        (0..4)
+            .flat_map(|y| {
+                (0..8).map(move |x| match (x < 2, y % 2, x % 2) {
+                    (true, _, _) => 0,
+                    (_, 0, 0) => 3000,
+                    (_, 1, 1) => 1500,
+                    (_, 0, _) => g1,
+                    _ => g2,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unequal_greens_are_equalised() {
+        let green = [[false, true], [true, false]];
+        let mut cfa = rggb(1100, 1200);
+        balance_greens(&mut cfa, 8, (2, 0, 6, 4), green, 100.0);
+        // The blue-row greens are scaled (above black) to the red-row mean
+        assert_eq!(cfa, rggb(1100, 1100));
+
+        // Equal greens stay as they are
+        let mut cfa = rggb(1000, 1000);
+        balance_greens(&mut cfa, 8, (2, 0, 6, 4), green, 100.0);
+        assert_eq!(cfa, rggb(1000, 1000));
+
+        // A large difference is scene content, not sensitivity
+        let mut cfa = rggb(1000, 2000);
+        balance_greens(&mut cfa, 8, (2, 0, 6, 4), green, 100.0);
+        assert_eq!(cfa, rggb(1000, 2000));
+    }
+
+    #[test]
+    fn pack_rgba_drops_partial_border_blocks() {
+        // 8x7 grey with a red last column and a blue last row, averaged in
+        // 3x3 blocks as for X-Trans: the bottom-right output must stay grey
+        // rather than average in the partial block at the edge
+        let (w, h) = (8, 7);
+        let raw: Vec<u8> = (0..w * h)
+            .flat_map(|i| match (i % w, i / w) {
+                (x, _) if x == w - 1 => [250, 0, 0],
+                (_, y) if y == h - 1 => [0, 0, 250],
+                _ => [100, 100, 100],
+            })
+            .collect();
+        let layout = RawLayout {
+            width: w,
+            height: h,
+            channels: 3,
+            bits: 8,
+        };
+        let out = pack_rgba(&raw, &layout, 3, 3, 1.0);
+        assert_eq!(out.len(), 2 * 2 * 4);
+        let corner = &out[12..15];
+        assert_rgb([corner[0], corner[1], corner[2]], [100.0 / 255.0; 3]);
+    }
+
+    fn context(monochrome_sensor: bool) -> PreviewContext {
+        PreviewContext {
+            width: 1,
+            height: 1,
+            data: vec![0.18, 0.18, 0.18, 1.0],
+            exif: ExifInfo::default(),
+            orientation: 0,
+            color_profile_missing: false,
+            baseline_exposure: 0.0,
+            wb_estimated: false,
+            daylight_wb: true,
+            vignette_profile: None,
+            as_shot_wb: None,
+            monochrome_sensor,
+            roi: None,
+        }
+    }
+
+    #[test]
+    fn monochrome_sensor_ignores_custom_white_balance() {
+        let custom = ImageParams {
+            wb_mode: WbMode::Custom,
+            temperature: 3000.0,
+            tint: 20.0,
+            ..Default::default()
+        };
+        let params = ImageParams {
+            layers: vec![Layer {
+                params: custom.clone(),
+                mask: MaskSpec::Global,
+                opacity: 1.0,
+            }],
+            ..custom
+        };
+
+        let grey = process([0.18; 3], &params.for_context(&context(true)));
+        assert_rgb(grey, [0.461356; 3]);
+
+        // Colour sensors keep it
+        let tinted = process([0.18; 3], &params.for_context(&context(false)));
+        assert!(tinted[0] != tinted[2]);
+    }
+
+    fn legacy_params(extra: &str) -> ImageParams {
+        serde_json::from_str(&format!(
+            r#"{{"exposure": 0.5, "contrast": 0.0, "highlights": 0.0, "shadows": 0.0,
+                "whites": 0.0, "blacks": 0.0, "saturation": 0.0, {}}}"#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn legacy_params_keep_their_white_balance() {
+        let params = legacy_params(r#""temperature": 3200, "tint": 0"#);
+        assert!(params.wb_mode == WbMode::Custom);
+        assert_eq!(params.temperature, 3200.0);
+        assert_eq!(params.exposure, 0.5);
+        assert!(legacy_params(r#""temperature": 5500, "tint": 12"#).wb_mode == WbMode::Custom);
+
+        // A neutral legacy balance is the as-shot default
+        assert!(legacy_params(r#""temperature": 5500, "tint": 0"#).wb_mode == WbMode::AsShot);
+        // An explicit mode always wins
+        let params = legacy_params(r#""temperature": 3200, "tint": 0, "wb_mode": "as_shot""#);
+        assert!(params.wb_mode == WbMode::AsShot);
+
+        let saved = ImageParams {
+            temperature: 4000.0,
+            ..Default::default()
+        };
+        let json = params_json(&saved).unwrap();
+        let loaded: ImageParams = serde_json::from_str(&json).unwrap();
+        assert!(loaded.wb_mode == WbMode::AsShot);
+        assert_eq!(loaded.temperature, 4000.0);
+    }
+
+    #[test]
+    fn curve_holds_its_endpoints() {
+        let points = [(0.1, 0.05), (0.5, 0.6), (0.9, 0.95)];
+        assert_eq!(eval_curve(&points, 0.1), 0.05);
+        assert_eq!(eval_curve(&points, 0.9), 0.95);
+        assert_close(eval_curve(&points, 0.5), 0.6);
+        // Flat outside the first and last point
+        assert_eq!(eval_curve(&points, 0.0), 0.05);
+        assert_eq!(eval_curve(&points, 1.0), 0.95);
+        // Fewer than two points is the identity
+        assert_eq!(eval_curve(&[], 0.3), 0.3);
+        assert_eq!(eval_curve(&[(0.5, 0.9)], 0.3), 0.3);
+    }
+
+    #[test]
+    fn curve_interpolation_is_monotonic() {
+        // A steep S-curve that a plain cubic spline would overshoot
+        let points = [(0.0, 0.0), (0.2, 0.05), (0.25, 0.9), (1.0, 1.0)];
+        let mut prev = 0.0;
+        for i in 0..=1000 {
+            let y = eval_curve(&points, i as f32 / 1000.0);
+            assert!(y >= prev, "curve falls at {}", i);
+            assert!((0.0..=1.0).contains(&y));
+            prev = y;
+        }
+        // Flat between equal outputs
+        let plateau = [(0.0, 0.0), (0.3, 0.5), (0.7, 0.5), (1.0, 1.0)];
+        assert_close(eval_curve(&plateau, 0.5), 0.5);
+    }
+
+    #[test]
+    fn curve_points_are_sorted_when_loaded() {
+        let params = legacy_params(r#""curve": [[0.8, 0.9], [0.2, 0.1], [0.5, 0.5], [0.2, 0.3]]"#);
+        // Sorted by input; the first point listed for an input wins
+        assert_eq!(params.curve, [(0.2, 0.1), (0.5, 0.5), (0.8, 0.9)]);
+        assert_close(eval_curve(&params.curve, 0.2), 0.1);
+    }
+
+    #[test]
+    fn params_without_a_curve_load_as_identity() {
+        // Saved before the tone curve existed
+        let params = legacy_params(r#""temperature": 5500, "tint": 0"#);
+        assert!(params.curve.is_empty());
+        assert_eq!(eval_curve(&params.curve, 0.42), 0.42);
+        let current = ImageParams {
+            exposure: 0.5,
+            ..Default::default()
+        };
+        assert_rgb(
+            process([0.2, 0.3, 0.4], &params),
+            process([0.2, 0.3, 0.4], &current),
+        );
+    }
+
+    fn snapshot(exposure: f32) -> ImageParams {
+        ImageParams {
+            exposure,
+            ..Default::default()
+        }
+    }
+
+    fn exposures(steps: impl Iterator<Item = Option<ImageParams>>) -> Vec<Option<f32>> {
+        steps.map(|p| p.map(|p| p.exposure)).collect()
+    }
+
+    #[test]
+    fn history_stops_at_both_ends() {
+        let mut history = ParamsHistory::default();
+        assert!(history.undo().is_none());
+        assert!(history.redo().is_none());
+
+        for ev in [0.0, 1.0, 2.0] {
+            history.push(snapshot(ev));
+        }
+        assert!(history.redo().is_none());
+        let undone = exposures((0..3).map(|_| history.undo()));
+        assert_eq!(undone, [Some(1.0), Some(0.0), None]);
+        assert_eq!(history.cursor, 0);
+        let redone = exposures((0..3).map(|_| history.redo()));
+        assert_eq!(redone, [Some(1.0), Some(2.0), None]);
+        assert_eq!(history.cursor, 2);
+    }
+
+    #[test]
+    fn push_after_undo_drops_the_redo_tail() {
+        let mut history = ParamsHistory::default();
+        for ev in [0.0, 1.0, 2.0, 3.0] {
+            history.push(snapshot(ev));
+        }
+        history.undo();
+        history.undo();
+        history.push(snapshot(5.0));
+
+        assert_eq!(history.entries.len(), 3);
+        assert!(history.redo().is_none());
+        let undone = exposures((0..3).map(|_| history.undo()));
+        assert_eq!(undone, [Some(1.0), Some(0.0), None]);
+    }
+
+    #[test]
+    fn history_evicts_the_oldest_entries() {
+        let mut history = ParamsHistory::default();
+        for i in 0..MAX_HISTORY + 5 {
+            history.push(snapshot(i as f32));
+        }
+        assert_eq!(history.entries.len(), MAX_HISTORY);
+        assert_eq!(history.cursor, MAX_HISTORY - 1);
+        assert_eq!(history.entries[0].exposure, 5.0);
+
+        // Undo reaches back to the oldest kept entry and no further
+        let undone = (0..MAX_HISTORY).filter_map(|_| history.undo()).last();
+        assert_eq!(undone.map(|p| p.exposure), Some(5.0));
+        assert_eq!(history.cursor, 0);
+    }
+
+    #[test]
+    fn xmp_values_are_mapped_and_clamped() {
+        let xml = r#"<rdf:Description crs:Exposure2012="+5.00" crs:Contrast2012="+50"
+            crs:Highlights2012="-250" crs:Shadows2012="+30" crs:Saturation="-100"
+            crs:WhiteBalance="Custom" crs:Temperature="50000" crs:Tint="+300"/>"#;
+        let params = params_from_xmp(xml);
+        assert_eq!(params.exposure, 3.0);
+        assert_eq!(params.contrast, 0.25);
+        assert_eq!(params.highlights, -1.0);
+        assert_close(params.shadows, 0.3);
+        assert_eq!(params.saturation, -1.0);
+        assert!(params.wb_mode == WbMode::Custom);
+        assert_eq!(params.temperature, wb::TEMPERATURE_RANGE.1);
+        assert_eq!(params.tint, wb::TINT_RANGE.0);
+    }
+
+    #[test]
+    fn xmp_as_shot_balance_is_kept() {
+        let xml = r#"<crs:WhiteBalance>As Shot</crs:WhiteBalance>
+            <crs:Temperature>3200</crs:Temperature><crs:Exposure2012>-0.5</crs:Exposure2012>"#;
+        let params = params_from_xmp(xml);
+        assert!(params.wb_mode == WbMode::AsShot);
+        assert_eq!(params.temperature, ImageParams::default().temperature);
+        assert_eq!(params.exposure, -0.5);
+        // Unparseable values leave the default
+        assert_eq!(
+            params_from_xmp(r#"crs:Exposure2012="bright""#).exposure,
+            0.0
+        );
+    }
+
+    #[test]
+    fn every_field_is_in_one_setting_group() {
+        let fields = params_object(&ImageParams::default()).unwrap();
+        for name in fields.keys() {
+            let groups: Vec<&str> = SETTING_GROUPS
+                .iter()
+                .filter(|(_, names)| names.contains(&name.as_str()))
+                .map(|(group, _)| *group)
+                .collect();
+            assert_eq!(groups.len(), 1, "{} is in groups {:?}", name, groups);
+        }
+        for (group, names) in SETTING_GROUPS {
+            assert!(
+                !fields.contains_key(*group),
+                "group {} shadows a field",
+                group
+            );
+            for name in *names {
+                assert!(
+                    fields.contains_key(*name),
+                    "{} in {} is not a field",
+                    name,
+                    group
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn roi_preview_reports_skipped_passes() {
+        let straightened = ImageParams {
+            straighten_angle: 2.0,
+            ..Default::default()
+        };
+        let denoised = ImageParams {
+            noise_reduction: 0.5,
+            ..Default::default()
+        };
+        let mut ctx = context(false);
+        assert!(!roi_skips_passes(&ctx, &straightened));
+
+        ctx.roi = Some(CropRect {
+            x: 0.25,
+            y: 0.25,
+            width: 0.5,
+            height: 0.5,
+        });
+        assert!(roi_skips_passes(&ctx, &straightened));
+        // Denoise isn't frame-relative, so it still runs on a crop
+        assert!(!roi_skips_passes(&ctx, &denoised));
+        assert_eq!(
+            prepare_buffer(&ctx, &straightened).as_ref(),
+            ctx.data.as_slice()
+        );
+    }
+
+    #[test]
+    fn linear_tiff_description_loads_as_params() {
+        let params = ImageParams {
+            exposure: 1.5,
+            wb_mode: WbMode::Custom,
+            ..Default::default()
+        };
+        let description = linear_tiff_description(&params, OutputSpace::Srgb).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&description).unwrap();
+        assert_eq!(value["transfer"], "linear");
+        let loaded: ImageParams = serde_json::from_str(&description).unwrap();
+        assert_eq!(params_json(&loaded), params_json(&params));
+    }
 }
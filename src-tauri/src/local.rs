@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use crate::wb;
+
+// Adjustments applied through a local mask, on linear data before the
+// global pipeline. exposure is in EV, temperature shifts the white balance by
+// that many kelvin (positive = warmer), contrast pivots around middle grey,
+// saturation scales the distance from luminance (-1 = greyscale).
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct LocalAdjustments {
+    pub exposure: f32,
+    pub temperature: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+}
+
+impl LocalAdjustments {
+    // Fully adjusted version of one pixel
+    fn adjusted(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut out = rgb;
+
+        if self.temperature != 0.0 {
+            let gains = wb::temperature_gains(wb::REFERENCE_KELVIN + self.temperature);
+            for (v, g) in out.iter_mut().zip(gains) {
+                *v *= g;
+            }
+        }
+
+        if self.exposure != 0.0 {
+            let mag = 2.0_f32.powf(self.exposure);
+            out.iter_mut().for_each(|v| *v *= mag);
+        }
+
+        if self.contrast != 0.0 {
+            let l = 0.2126 * out[0] + 0.7152 * out[1] + 0.0722 * out[2];
+            if l > 0.0 {
+                let target = 0.18 * (l / 0.18).powf(1.0 + self.contrast);
+                let scale = target / l;
+                out.iter_mut().for_each(|v| *v *= scale);
+            }
+        }
+
+        if self.saturation != 0.0 {
+            let l = 0.2126 * out[0] + 0.7152 * out[1] + 0.0722 * out[2];
+            let s = 1.0 + self.saturation;
+            out.iter_mut()
+                .for_each(|v| *v = (l + (*v - l) * s).max(0.0));
+        }
+        out
+    }
+
+    // Blends the adjusted pixel with the original by the mask weight
+    fn apply(&self, px: &mut [f32], weight: f32) {
+        if weight <= 0.0 {
+            return;
+        }
+        let adjusted = self.adjusted([px[0], px[1], px[2]]);
+        for (v, a) in px.iter_mut().zip(adjusted) {
+            *v += (a - *v) * weight;
+        }
+    }
+}
+
+// Graduated filter: full strength on the `start` side, fading out linearly
+// (smoothed) to nothing at `end`. Points are normalized image coordinates.
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct GradientFilter {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub adjustments: LocalAdjustments,
+}
+
+impl GradientFilter {
+    fn weight(&self, x: f32, y: f32, w: f32, h: f32) -> f32 {
+        // Project in pixels so the falloff stays perpendicular to the line
+        let (sx, sy) = (self.start.0 * w, self.start.1 * h);
+        let (dx, dy) = (self.end.0 * w - sx, self.end.1 * h - sy);
+        let len2 = dx * dx + dy * dy;
+        if len2 <= 0.0 {
+            return 0.0;
+        }
+        let t = (((x - sx) * dx + (y - sy) * dy) / len2).clamp(0.0, 1.0);
+        1.0 - t * t * (3.0 - 2.0 * t)
+    }
+}
+
+// Elliptical filter: full strength inside the ellipse, fading out over the
+// outer `feather` fraction of the radii. `invert` adjusts the outside instead.
+// Center and radii are normalized to the image width/height.
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct RadialFilter {
+    pub center: (f32, f32),
+    pub radius_x: f32,
+    pub radius_y: f32,
+    pub feather: f32,
+    pub invert: bool,
+    pub adjustments: LocalAdjustments,
+}
+
+impl RadialFilter {
+    fn weight(&self, x: f32, y: f32, w: f32, h: f32) -> f32 {
+        let weight = if self.radius_x <= 0.0 || self.radius_y <= 0.0 {
+            0.0
+        } else {
+            let dx = (x / w - self.center.0) / self.radius_x;
+            let dy = (y / h - self.center.1) / self.radius_y;
+            let d = (dx * dx + dy * dy).sqrt();
+            let feather = self.feather.clamp(0.0, 1.0);
+            if d <= 1.0 - feather {
+                1.0
+            } else if d >= 1.0 {
+                0.0
+            } else {
+                let t = (1.0 - d) / feather;
+                t * t * (3.0 - 2.0 * t)
+            }
+        };
+        if self.invert {
+            1.0 - weight
+        } else {
+            weight
+        }
+    }
+}
+
+pub fn apply_local(
+    data: &mut [f32],
+    w: usize,
+    h: usize,
+    gradients: &[GradientFilter],
+    radials: &[RadialFilter],
+) {
+    for (i, px) in data.chunks_exact_mut(4).enumerate() {
+        let x = (i % w) as f32;
+        let y = (i / w) as f32;
+        for gradient in gradients {
+            let weight = gradient.weight(x, y, w as f32, h as f32);
+            gradient.adjustments.apply(px, weight);
+        }
+        for radial in radials {
+            let weight = radial.weight(x, y, w as f32, h as f32);
+            radial.adjustments.apply(px, weight);
+        }
+    }
+}
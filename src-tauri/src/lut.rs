@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// 3D LUT from an Adobe/Resolve .cube file, applied to display-encoded RGB
+pub struct Lut3d {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    table: Vec<[f32; 3]>, // red varies fastest, then green, then blue
+}
+
+const MAX_SIZE: usize = 256;
+
+fn parse_triplet(parts: &[&str], line_no: usize) -> Result<[f32; 3], String> {
+    if parts.len() != 3 {
+        return Err(format!("Line {}: expected three values", line_no));
+    }
+    let mut out = [0.0; 3];
+    for (v, part) in out.iter_mut().zip(parts) {
+        *v = part
+            .parse()
+            .map_err(|_| format!("Line {}: invalid number {:?}", line_no, part))?;
+    }
+    Ok(out)
+}
+
+pub fn parse_cube(text: &str) -> Result<Lut3d, String> {
+    let mut size = None;
+    let mut domain_min = [0.0; 3];
+    let mut domain_max = [1.0; 3];
+    let mut table = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts[0] {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => return Err("1D LUTs are not supported".into()),
+            "LUT_3D_SIZE" => {
+                let n: usize = parts
+                    .get(1)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or(format!("Line {}: invalid LUT_3D_SIZE", line_no))?;
+                if !(2..=MAX_SIZE).contains(&n) {
+                    return Err(format!("Unsupported LUT_3D_SIZE {}", n));
+                }
+                size = Some(n);
+            }
+            "DOMAIN_MIN" => domain_min = parse_triplet(&parts[1..], line_no)?,
+            "DOMAIN_MAX" => domain_max = parse_triplet(&parts[1..], line_no)?,
+            // Resolve's form of the domain, one range for all channels
+            "LUT_3D_INPUT_RANGE" => {
+                let range: Vec<f32> = parts[1..].iter().filter_map(|v| v.parse().ok()).collect();
+                let [min, max] = range[..] else {
+                    return Err(format!("Line {}: invalid LUT_3D_INPUT_RANGE", line_no));
+                };
+                domain_min = [min; 3];
+                domain_max = [max; 3];
+            }
+            // Other keywords (LUT_1D_INPUT_RANGE, vendor extensions) don't
+            // affect a 3D table
+            keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+            _ => {
+                if size.is_none() {
+                    return Err("LUT data before LUT_3D_SIZE".into());
+                }
+                table.push(parse_triplet(&parts, line_no)?);
+            }
+        }
+    }
+
+    let size = size.ok_or("Missing LUT_3D_SIZE")?;
+    if table.len() != size * size * size {
+        return Err(format!(
+            "Expected {} LUT entries for size {}, found {}",
+            size * size * size,
+            size,
+            table.len()
+        ));
+    }
+    if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+        return Err("DOMAIN_MAX must be above DOMAIN_MIN".into());
+    }
+    Ok(Lut3d {
+        size,
+        domain_min,
+        domain_max,
+        table,
+    })
+}
+
+impl Lut3d {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn domain(&self) -> ([f32; 3], [f32; 3]) {
+        (self.domain_min, self.domain_max)
+    }
+
+    pub fn table(&self) -> &[[f32; 3]] {
+        &self.table
+    }
+
+    // Trilinear interpolation; inputs outside the domain are clamped to it
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let n = self.size;
+        let max = (n - 1) as f32;
+        let mut base = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+        for c in 0..3 {
+            let t = (rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]);
+            let pos = t.clamp(0.0, 1.0) * max;
+            base[c] = (pos.floor() as usize).min(n - 2);
+            frac[c] = pos - base[c] as f32;
+        }
+
+        let at = |r: usize, g: usize, b: usize| self.table[(b * n + g) * n + r];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        };
+        let [r, g, b] = base;
+        let [fr, fg, fb] = frac;
+        let c00 = lerp(at(r, g, b), at(r + 1, g, b), fr);
+        let c10 = lerp(at(r, g + 1, b), at(r + 1, g + 1, b), fr);
+        let c01 = lerp(at(r, g, b + 1), at(r + 1, g, b + 1), fr);
+        let c11 = lerp(at(r, g + 1, b + 1), at(r + 1, g + 1, b + 1), fr);
+        lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb)
+    }
+}
+
+// Last parsed LUT, keyed by path and modification time so an edited file
+// is picked up but repeated exports don't re-read it
+static CACHE: Mutex<Option<(PathBuf, Option<SystemTime>, Arc<Lut3d>)>> = Mutex::new(None);
+
+pub fn load(path: &str) -> Result<Arc<Lut3d>, String> {
+    let path = Path::new(path);
+    let modified = std::fs::metadata(path)
+        .map_err(|e| e.to_string())?
+        .modified()
+        .ok();
+
+    let mut cache = CACHE.lock().unwrap();
+    if let Some((cached_path, cached_modified, lut)) = cache.as_ref() {
+        if cached_path == path && *cached_modified == modified {
+            return Ok(lut.clone());
+        }
+    }
+
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let lut = Arc::new(parse_cube(&text)?);
+    *cache = Some((path.to_path_buf(), modified, lut.clone()));
+    Ok(lut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // .cube text of an identity LUT of the given size
+    fn identity_cube(size: usize, header: &str) -> String {
+        let max = (size - 1) as f32;
+        let mut text = format!("TITLE \"identity\"\n{}LUT_3D_SIZE {}\n", header, size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    text += &format!("{} {} {}\n", r as f32 / max, g as f32 / max, b as f32 / max);
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn parses_size_and_domain() {
+        let lut = parse_cube(&identity_cube(3, "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 1 2 4\n")).unwrap();
+        assert_eq!(lut.size(), 3);
+        assert_eq!(lut.domain(), ([0.0; 3], [1.0, 2.0, 4.0]));
+        assert_eq!(lut.table().len(), 27);
+        assert_eq!(lut.table()[1], [0.5, 0.0, 0.0]);
+
+        let lut = parse_cube(&identity_cube(2, "LUT_3D_INPUT_RANGE -0.5 1.5\n")).unwrap();
+        assert_eq!(lut.domain(), ([-0.5; 3], [1.5; 3]));
+
+        // Unknown keywords and comments are skipped
+        let lut = parse_cube(&identity_cube(2, "# comment\nLUT_1D_INPUT_RANGE 0 1\n")).unwrap();
+        assert_eq!(lut.domain(), ([0.0; 3], [1.0; 3]));
+    }
+
+    #[test]
+    fn rejects_wrong_entry_counts() {
+        let mut text = identity_cube(3, "");
+        text += "0 0 0\n";
+        let err = parse_cube(&text).err().unwrap();
+        assert!(err.contains("Expected 27"), "{err}");
+
+        let text = identity_cube(3, "");
+        let short: Vec<&str> = text.lines().collect();
+        assert!(parse_cube(&short[..short.len() - 1].join("\n")).is_err());
+
+        assert!(parse_cube("LUT_3D_SIZE 2\n0 0\n").is_err());
+        assert!(parse_cube("0 0 0\nLUT_3D_SIZE 2\n").is_err());
+        assert!(parse_cube("TITLE \"empty\"\n").is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_sizes_and_domains() {
+        for size in ["0", "1", "257", "-2", "abc"] {
+            assert!(
+                parse_cube(&format!("LUT_3D_SIZE {}\n", size)).is_err(),
+                "{size}"
+            );
+        }
+        assert!(parse_cube("LUT_1D_SIZE 16\n").is_err());
+        assert!(parse_cube(&identity_cube(2, "DOMAIN_MIN 1 0 0\nDOMAIN_MAX 1 1 1\n")).is_err());
+        assert!(parse_cube(&identity_cube(2, "LUT_3D_INPUT_RANGE 0\n")).is_err());
+    }
+
+    #[test]
+    fn identity_lut_round_trips() {
+        let lut = parse_cube(&identity_cube(5, "")).unwrap();
+        for rgb in [[0.0; 3], [1.0; 3], [0.1, 0.55, 0.9], [0.33, 0.66, 0.01]] {
+            let out = lut.apply(rgb);
+            for (o, i) in out.iter().zip(rgb) {
+                assert!((o - i).abs() < 1e-5, "{rgb:?} -> {out:?}");
+            }
+        }
+        // Outside the domain clamps to its edge
+        assert_eq!(lut.apply([-1.0, 2.0, 0.5]), [0.0, 1.0, 0.5]);
+    }
+}
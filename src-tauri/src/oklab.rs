@@ -0,0 +1,36 @@
+// Björn Ottosson's OKLab: a perceptual space where scaling (a, b) changes
+// chroma with little hue or lightness drift. Defined on linear sRGB; for the
+// wider output spaces it's an approximation.
+
+pub fn from_linear_rgb(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb;
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+pub fn to_linear_rgb(lab: [f32; 3]) -> [f32; 3] {
+    let [lightness, a, b] = lab;
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+}
+
+// Scales chroma by `mult` and rotates hue by `degrees` at constant lightness
+pub fn adjust(rgb: &mut [f32; 3], mult: f32, degrees: f32) {
+    let [lightness, a, b] = from_linear_rgb(*rgb);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let a2 = (a * cos - b * sin) * mult;
+    let b2 = (a * sin + b * cos) * mult;
+    *rgb = to_linear_rgb([lightness, a2, b2]);
+}
@@ -0,0 +1,98 @@
+// Colour temperature white balance on the Planckian (blackbody) locus.
+// The custom WB gains take light of the given temperature to the 5500K
+// reference that the decode's daylight multipliers already balance for.
+
+pub const REFERENCE_KELVIN: f32 = 5500.0;
+const MIN_KELVIN: f32 = 1667.0;
+const MAX_KELVIN: f32 = 25000.0;
+
+// Range of the temperature and tint sliders; anything computed for the UI
+// is clamped to these
+pub const TEMPERATURE_RANGE: (f32, f32) = (2000.0, 10000.0);
+pub const TINT_RANGE: (f32, f32) = (-50.0, 50.0);
+
+// Linear sRGB colour (Y = 1) of a blackbody at `kelvin`, using the Kim et al.
+// cubic approximation of the Planckian locus chromaticity
+fn kelvin_to_rgb(kelvin: f32) -> [f32; 3] {
+    let t = kelvin.clamp(MIN_KELVIN, MAX_KELVIN) as f64;
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    let big_x = x / y;
+    let big_z = (1.0 - x - y) / y;
+    // Very warm light falls outside sRGB (blue goes negative); keep a floor
+    // so the gains stay finite
+    [
+        ((3.2406 * big_x - 1.5372 - 0.4986 * big_z) as f32).max(0.01),
+        ((-0.9689 * big_x + 1.8758 + 0.0415 * big_z) as f32).max(0.01),
+        ((0.0557 * big_x - 0.2040 + 1.0570 * big_z) as f32).max(0.01),
+    ]
+}
+
+// RGB gains (green = 1) that neutralize light of `kelvin` relative to the
+// reference. Higher temperatures give warmer results, as in other editors.
+pub fn temperature_gains(kelvin: f32) -> [f32; 3] {
+    let reference = kelvin_to_rgb(REFERENCE_KELVIN);
+    let light = kelvin_to_rgb(kelvin);
+    let gain = |c: usize| (reference[c] / light[c]) / (reference[1] / light[1]);
+    [gain(0), 1.0, gain(2)]
+}
+
+// (temperature, tint) for the standard white balance presets. Tint is our
+// green gain offset, so the green cast of fluorescent light needs a
+// negative value.
+pub fn preset(name: &str) -> Option<(f32, f32)> {
+    match name.to_ascii_lowercase().as_str() {
+        "daylight" => Some((5500.0, 0.0)),
+        "cloudy" => Some((6500.0, 0.0)),
+        "shade" => Some((7500.0, 0.0)),
+        "tungsten" => Some((3200.0, 0.0)),
+        "fluorescent" => Some((4000.0, -7.0)),
+        "flash" => Some((5500.0, 0.0)),
+        _ => None,
+    }
+}
+
+// Temperature whose gains make a pixel with this blue/red ratio neutral.
+// The red/blue gain ratio grows monotonically with temperature, so bisect
+// (in mireds, which are perceptually more even than kelvin).
+pub fn temperature_for_ratio(blue_over_red: f32, min: f32, max: f32) -> f32 {
+    let mut lo = 1.0e6 / max;
+    let mut hi = 1.0e6 / min;
+    for _ in 0..40 {
+        let mid = (lo + hi) / 2.0;
+        let gains = temperature_gains(1.0e6 / mid);
+        // Balanced when r * gain_r == b * gain_b
+        if gains[0] / gains[2] > blue_over_red {
+            lo = mid; // too warm, lower the temperature (raise mireds)
+        } else {
+            hi = mid;
+        }
+    }
+    1.0e6 / ((lo + hi) / 2.0)
+}
+
+// (temperature, tint) whose gains best match `gains` (RGB, relative to the
+// reference): temperature from the red/blue ratio, tint from the green left
+// over once red and blue are matched on average. Clamped to the slider range.
+pub fn temperature_tint_for_gains(gains: [f32; 3]) -> (f32, f32) {
+    let (min, max) = TEMPERATURE_RANGE;
+    let temperature = temperature_for_ratio(gains[0] / gains[2], min, max);
+    let model = temperature_gains(temperature);
+    let scale = ((gains[0] * gains[2]) / (model[0] * model[2])).sqrt();
+    let tint = ((gains[1] / scale - 1.0) * 100.0).clamp(TINT_RANGE.0, TINT_RANGE.1);
+    (temperature, tint)
+}
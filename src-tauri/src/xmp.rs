@@ -0,0 +1,61 @@
+// Minimal reader for Camera Raw settings (crs: namespace) in XMP sidecars.
+// Values are found by name rather than by parsing the RDF, which covers
+// both forms Adobe writes: attributes (crs:Exposure2012="+0.50") and
+// elements (<crs:Exposure2012>+0.50</crs:Exposure2012>).
+
+pub fn crs_value<'a>(xml: &'a str, field: &str) -> Option<&'a str> {
+    let attr = format!("crs:{}=", field);
+    if let Some(pos) = xml.find(&attr) {
+        let rest = &xml[pos + attr.len()..];
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let rest = &rest[1..];
+        return rest.find(quote).map(|end| rest[..end].trim());
+    }
+
+    let open = format!("<crs:{}>", field);
+    let pos = xml.find(&open)?;
+    let rest = &xml[pos + open.len()..];
+    rest.find('<').map(|end| rest[..end].trim())
+}
+
+pub fn crs_number(xml: &str, field: &str) -> Option<f32> {
+    crs_value(xml, field)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_attribute_form() {
+        let xml = r#"<rdf:Description crs:Exposure2012="+0.50" crs:Tint='-12'
+            crs:WhiteBalance=" Custom "/>"#;
+        assert_eq!(crs_value(xml, "Exposure2012"), Some("+0.50"));
+        assert_eq!(crs_number(xml, "Exposure2012"), Some(0.5));
+        assert_eq!(crs_number(xml, "Tint"), Some(-12.0));
+        assert_eq!(crs_value(xml, "WhiteBalance"), Some("Custom"));
+    }
+
+    #[test]
+    fn reads_element_form() {
+        let xml =
+            "<crs:Contrast2012>\n  +25\n</crs:Contrast2012><crs:Shadows2012>-40</crs:Shadows2012>";
+        assert_eq!(crs_number(xml, "Contrast2012"), Some(25.0));
+        assert_eq!(crs_number(xml, "Shadows2012"), Some(-40.0));
+    }
+
+    #[test]
+    fn missing_and_malformed_values_are_none() {
+        let xml = r#"<rdf:Description crs:Exposure2012=+0.50 crs:Saturation="high"
+            crs:Whites2012="+10"/>"#;
+        assert_eq!(crs_value(xml, "Blacks2012"), None);
+        // Unquoted attribute
+        assert_eq!(crs_value(xml, "Exposure2012"), None);
+        assert_eq!(crs_number(xml, "Saturation"), None);
+        // A name is not matched as the suffix of another
+        assert_eq!(crs_number(xml, "Whites2012"), Some(10.0));
+        assert_eq!(crs_number(xml, "Whites"), None);
+        // Unterminated
+        assert_eq!(crs_value(r#"crs:Tint="5"#, "Tint"), None);
+    }
+}
@@ -1,12 +1,137 @@
 import { useState, useRef, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { open, save } from "@tauri-apps/plugin-dialog";
 import "./App.css";
 
+interface ExifInfo {
+  make: string | null;
+  model: string | null;
+  lens: string | null;
+  iso: number | null;
+  shutter: number | null; // seconds
+  aperture: number | null;
+  focal_length: number | null; // mm
+  timestamp: number | null; // unix seconds
+}
+
 interface ImageResult {
   width: number;
   height: number;
-  data: number[]; // Linear RGB Float array (flat)
+  data: Float32Array; // Linear RGBA Float array (flat)
+  exif: ExifInfo;
+  orientation: number; // camera orientation, clockwise degrees
+  color_profile_missing?: boolean; // no camera matrix, colours may be off
+  baseline_exposure?: number; // camera default EV, already in data
+  wb_estimated?: boolean; // no as-shot WB in the file, daylight used
+  vignette_profile_found?: boolean; // file has a manufacturer vignetting profile
+  as_shot_wb?: [number, number] | null; // camera WB as [temperature, tint]
+  monochrome_sensor?: boolean; // no CFA, white balance doesn't apply
+  passes_skipped?: boolean; // render of a crop: frame-relative corrections left out
+}
+
+// Structured backend error (AppError): { kind, message? }
+interface AppError {
+  kind: 'io' | 'unsupported_format' | 'decode' | 'encode' | 'invalid_input' | 'no_image_loaded' | 'cancelled';
+  message?: string;
+}
+
+// A newer load_raw superseded this one; nothing to report
+function isCancelled(e: unknown): boolean {
+  return !!e && typeof e === 'object' && (e as AppError).kind === 'cancelled';
+}
+
+function errorMessage(e: unknown): string {
+  if (e && typeof e === 'object' && 'kind' in e) {
+    const err = e as AppError;
+    switch (err.kind) {
+      case 'unsupported_format':
+        return `This camera or file format is not supported (${err.message})`;
+      case 'io':
+        return `Could not read the file (${err.message})`;
+      case 'no_image_loaded':
+        return 'No image loaded';
+      default:
+        return err.message ?? err.kind;
+    }
+  }
+  return String(e);
+}
+
+// load_raw and get_lut reply with a binary payload:
+// [u32 LE header length][JSON header][pad to 4 bytes][f32 LE data]
+function decodeBinaryResponse<T>(buffer: ArrayBuffer): T & { data: Float32Array } {
+  const view = new DataView(buffer);
+  const headerLen = view.getUint32(0, true);
+  const headerBytes = new Uint8Array(buffer, 4, headerLen);
+  const header = JSON.parse(new TextDecoder().decode(headerBytes));
+  const dataOffset = Math.ceil((4 + headerLen) / 4) * 4;
+  const data = new Float32Array(buffer.slice(dataOffset));
+  return { ...header, data };
+}
+
+function decodeImageResponse(buffer: ArrayBuffer): ImageResult {
+  return decodeBinaryResponse<Omit<ImageResult, 'data'>>(buffer);
+}
+
+// 3D LUT of apply_lut (see get_lut): size^3 RGB entries, red fastest
+interface LutData {
+  size: number;
+  domain_min: [number, number, number];
+  domain_max: [number, number, number];
+  data: Float32Array;
+}
+
+// Trilinear lookup on display-encoded RGB, mirrors Lut3d::apply in lut.rs
+function applyLut(lut: LutData, rgb: number[]): [number, number, number] {
+  const n = lut.size;
+  const base = [0, 0, 0];
+  const frac = [0, 0, 0];
+  for (let c = 0; c < 3; c++) {
+    const t = (rgb[c] - lut.domain_min[c]) / (lut.domain_max[c] - lut.domain_min[c]);
+    const pos = Math.min(1, Math.max(0, t)) * (n - 1);
+    base[c] = Math.min(Math.floor(pos), n - 2);
+    frac[c] = pos - base[c];
+  }
+  const out: [number, number, number] = [0, 0, 0];
+  for (let c = 0; c < 3; c++) {
+    const at = (r: number, g: number, b: number) => lut.data[((b * n + g) * n + r) * 3 + c];
+    const lerp = (a: number, b: number, t: number) => a + (b - a) * t;
+    const [r, g, b] = base;
+    const [fr, fg, fb] = frac;
+    const c00 = lerp(at(r, g, b), at(r + 1, g, b), fr);
+    const c10 = lerp(at(r, g + 1, b), at(r + 1, g + 1, b), fr);
+    const c01 = lerp(at(r, g, b + 1), at(r + 1, g, b + 1), fr);
+    const c11 = lerp(at(r, g + 1, b + 1), at(r + 1, g + 1, b + 1), fr);
+    out[c] = lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb);
+  }
+  return out;
+}
+
+// LUT laid out for a 2D texture (WebGL 1 has no 3D ones): each blue slice is
+// a size x size tile, `cols` tiles per row. 8 bits per channel is enough for
+// the preview.
+function lutAtlas(lut: LutData): { pixels: Uint8Array; cols: number; width: number; height: number } {
+  const n = lut.size;
+  const cols = Math.ceil(Math.sqrt(n));
+  const width = cols * n;
+  const height = Math.ceil(n / cols) * n;
+  const pixels = new Uint8Array(width * height * 4);
+  for (let b = 0; b < n; b++) {
+    const x0 = (b % cols) * n;
+    const y0 = Math.floor(b / cols) * n;
+    for (let g = 0; g < n; g++) {
+      for (let r = 0; r < n; r++) {
+        const src = ((b * n + g) * n + r) * 3;
+        const dst = ((y0 + g) * width + x0 + r) * 4;
+        for (let c = 0; c < 3; c++) {
+          pixels[dst + c] = Math.min(255, Math.max(0, Math.round(lut.data[src + c] * 255)));
+        }
+        pixels[dst + 3] = 255;
+      }
+    }
+  }
+  return { pixels, cols, width, height };
 }
 
 interface WebGLParams {
@@ -19,6 +144,122 @@ interface WebGLParams {
   whites: number;
   blacks: number;
   saturation: number;
+  // 'as_shot' decodes with the camera WB and ignores temperature/tint;
+  // 'custom' decodes with daylight WB and uses temperature/tint
+  wb_mode?: 'as_shot' | 'custom';
+  // Luminance band of the shadows / highlights sliders (backend default 0.6)
+  shadow_range?: number;
+  highlight_range?: number;
+  straighten_angle?: number; // degrees, clockwise
+  transfer_curve?: 'srgb' | 'gamma22';
+  tone_mapping?: 'none' | 'reinhard' | 'aces';
+  adjustment_space?: 'rgb' | 'oklab'; // space saturation works in
+  film_negative?: boolean;
+  film_base?: [number, number, number]; // linear RGB of the orange mask
+  auto_orient?: boolean;
+  apply_lut?: string | null; // .cube file applied after the output gamma
+  layers?: AdjustmentLayer[];
+  // Steps FS_SOURCE doesn't mirror (see needsBackendRender)
+  vibrance?: number;
+  curve?: [number, number][]; // (input, output) in 0..1 display space
+  hsl?: Record<string, { hue?: number; saturation?: number; luminance?: number }>;
+  shadow_sat?: number; // split toning strength of each tint
+  highlight_sat?: number;
+  monochrome?: boolean;
+  red_gain?: number;
+  green_gain?: number;
+  blue_gain?: number;
+  gamut_compress?: boolean;
+}
+
+// Adjustment layer (Layer in lib.rs); only whole-image masks exist so far
+interface AdjustmentLayer {
+  params: WebGLParams;
+  mask?: 'global';
+  opacity?: number; // 0..1, default 1
+}
+
+// Layers the shader has room for; more are left out of the preview
+const MAX_PREVIEW_LAYERS = 8;
+
+// Layers that change the result, with opacity clamped as apply_layers does
+function visibleLayers(params: WebGLParams): { params: WebGLParams; opacity: number }[] {
+  return (params.layers ?? [])
+    .map((layer) => ({ params: layer.params, opacity: Math.min(1, Math.max(0, layer.opacity ?? 1)) }))
+    .filter((layer) => layer.opacity > 0);
+}
+
+// Params using steps the shader doesn't have (or more layers than it has
+// room for). The preview is then rendered by the backend (render_preview).
+function needsBackendRender(params: WebGLParams): boolean {
+  const hsl = Object.values(params.hsl ?? {})
+    .some((band) => !!(band.hue || band.saturation || band.luminance));
+  const gains = [params.red_gain, params.green_gain, params.blue_gain]
+    .some((gain) => (gain ?? 1) !== 1);
+  const layers = visibleLayers(params);
+  return !!params.vibrance
+    || (params.curve?.length ?? 0) >= 2
+    || hsl
+    || !!params.shadow_sat
+    || !!params.highlight_sat
+    || !!params.monochrome
+    || gains
+    || !!params.gamut_compress
+    || layers.length > MAX_PREVIEW_LAYERS
+    || layers.some((layer) => needsBackendRender(layer.params));
+}
+
+// Params as they apply to the image: no custom WB on a monochrome sensor,
+// layers included (mirrors ImageParams::for_context)
+function forImage(params: WebGLParams, image: ImageResult): WebGLParams {
+  if (!image.monochrome_sensor) return params;
+  return {
+    ...params,
+    wb_mode: 'as_shot',
+    layers: params.layers?.map((layer) => ({ ...layer, params: { ...layer.params, wb_mode: 'as_shot' } })),
+  };
+}
+
+// Zoom that keeps a w x h frame rotated by `radians` free of empty corners
+// (mirrors apply_straighten in filters.rs)
+function straightenScale(w: number, h: number, radians: number): number {
+  const c = Math.abs(Math.cos(radians));
+  const s = Math.abs(Math.sin(radians));
+  return Math.min(w / (w * c + h * s), h / (w * s + h * c));
+}
+
+function toneRange(range: number | undefined): number {
+  return Math.min(1.0, Math.max(0.05, range ?? 0.6));
+}
+
+// Linear sRGB of a blackbody (Planckian locus, Kim et al.), mirrors wb.rs
+function kelvinToRgb(kelvin: number): [number, number, number] {
+  const t = Math.min(25000, Math.max(1667, kelvin));
+  const x = t <= 4000
+    ? -0.2661239e9 / t ** 3 - 0.2343589e6 / t ** 2 + 0.8776956e3 / t + 0.179910
+    : -3.0258469e9 / t ** 3 + 2.1070379e6 / t ** 2 + 0.2226347e3 / t + 0.240390;
+  const y = t <= 2222
+    ? -1.1063814 * x ** 3 - 1.34811020 * x ** 2 + 2.18555832 * x - 0.20219683
+    : t <= 4000
+      ? -0.9549476 * x ** 3 - 1.37418593 * x ** 2 + 2.09137015 * x - 0.16748867
+      : 3.0817580 * x ** 3 - 5.87338670 * x ** 2 + 3.75112997 * x - 0.37001483;
+  const X = x / y;
+  const Z = (1 - x - y) / y;
+  return [
+    Math.max(0.01, 3.2406 * X - 1.5372 - 0.4986 * Z),
+    Math.max(0.01, -0.9689 * X + 1.8758 + 0.0415 * Z),
+    Math.max(0.01, 0.0557 * X - 0.2040 + 1.0570 * Z),
+  ];
+}
+
+function whiteBalanceGains(params: WebGLParams): [number, number, number] {
+  if (params.wb_mode !== 'custom') return [1.0, 1.0, 1.0];
+  const ref = kelvinToRgb(5500.0);
+  const light = kelvinToRgb(params.temperature);
+  const wb_r = (ref[0] / light[0]) / (ref[1] / light[1]);
+  const wb_b = (ref[2] / light[2]) / (ref[1] / light[1]);
+  const wb_g = 1.0 + params.tint / 100.0;
+  return [wb_r, wb_g, wb_b];
 }
 
 interface HistogramData {
@@ -43,108 +284,236 @@ const FS_SOURCE = `
   precision mediump float;
   varying vec2 v_texCoord;
   uniform sampler2D u_image;
+  uniform float u_rendered; // 1 = u_image is the backend's final render (render_preview)
+  uniform float u_straighten; // radians, clockwise
+  uniform float u_straightenScale;
+  uniform float u_aspect; // width / height
   
-  uniform float u_exposure;
-  uniform float u_contrast;
-  uniform vec3 u_whiteBalance;
-  
-  // Advanced
-  uniform float u_saturation;
-  uniform float u_highlights;
-  uniform float u_shadows;
-  uniform float u_whites;
-  uniform float u_blacks;
+  // Per-pixel adjustments of one set of params (see setAdjustUniforms)
+  struct Adjust {
+    float filmNegative;
+    vec3 filmBase;
+    vec3 whiteBalance;
+    float exposure;
+    float contrast;
+    float highlights;
+    float shadows;
+    float shadowRange;
+    float highlightRange;
+    float saturation;
+    float oklab; // 1 = saturation scales OKLab chroma (see oklab.rs)
+    float whites;
+    float blacks;
+  };
+  uniform Adjust u_base;
+  // Adjustment layers, applied in order on top of u_base (see apply_layers)
+  uniform Adjust u_layers[${MAX_PREVIEW_LAYERS}];
+  uniform float u_layerOpacity[${MAX_PREVIEW_LAYERS}];
+  uniform int u_layerCount;
+
+  uniform float u_srgbCurve; // 1 = exact sRGB curve, 0 = pure 2.2
+  uniform float u_toneMap; // 0 = none, 1 = Reinhard, 2 = ACES (see ToneMapping)
+
+  // 3D LUT as a 2D atlas of blue slices (see lutAtlas)
+  uniform float u_hasLut;
+  uniform sampler2D u_lut;
+  uniform float u_lutSize;
+  uniform float u_lutCols;
+  uniform vec2 u_lutTexSize;
+  uniform vec3 u_lutDomainMin;
+  uniform vec3 u_lutDomainMax;
   
   float getLuminance(vec3 color) {
     return dot(color, vec3(0.2126, 0.7152, 0.0722));
   }
+
+  // Scales OKLab chroma of a linear sRGB colour
+  vec3 oklabSaturate(vec3 rgb, float mult) {
+    vec3 lms = pow(mat3(0.4122214708, 0.2119034982, 0.0883024619,
+                        0.5363325363, 0.6806995451, 0.2817188376,
+                        0.0514459929, 0.1073969566, 0.6299787005) * rgb, vec3(1.0 / 3.0));
+    vec3 lab = mat3(0.2104542553, 1.9779984951, 0.0259040371,
+                    0.7936177850, -2.4285922050, 0.7827717662,
+                    -0.0040720468, 0.4505937099, -0.8086757660) * lms;
+    lab.yz *= mult;
+    lms = mat3(1.0, 1.0, 1.0,
+               0.3963377774, -0.1055613458, -0.0894841775,
+               0.2158037573, -0.0638541728, -1.2914855480) * lab;
+    return mat3(4.0767416621, -1.2684380046, -0.0041960863,
+                -3.3077115913, 2.6097574011, -0.7034186147,
+                0.2309699292, -0.3413193965, 1.7076147010) * (lms * lms * lms);
+  }
   
-  void main() {
-    vec4 color = texture2D(u_image, v_texCoord);
-    vec3 rgb = color.rgb;
+  // One blue slice at texel position rg; LINEAR filtering interpolates red
+  // and green, and texel centres keep it inside the tile
+  vec3 lutSlice(float slice, vec2 rg) {
+    float row = floor(slice / u_lutCols);
+    float col = slice - row * u_lutCols;
+    vec2 texel = vec2(col, row) * u_lutSize + rg + 0.5;
+    return texture2D(u_lut, texel / u_lutTexSize).rgb;
+  }
+
+  // Trilinear, like Lut3d::apply
+  vec3 applyLut(vec3 rgb) {
+    vec3 t = clamp((rgb - u_lutDomainMin) / (u_lutDomainMax - u_lutDomainMin), 0.0, 1.0);
+    vec3 pos = t * (u_lutSize - 1.0);
+    float b0 = min(floor(pos.b), u_lutSize - 2.0);
+    return mix(lutSlice(b0, pos.rg), lutSlice(b0 + 1.0, pos.rg), pos.b - b0);
+  }
+  
+  // Steps 0-7 of apply_adjustments, as far as the preview mirrors them
+  vec3 adjust(vec3 rgb, Adjust a) {
+    // 0. Film negative inversion against the base colour
+    if (a.filmNegative > 0.5) {
+      rgb = max(1.0 - rgb / max(a.filmBase, 1e-6), 0.0);
+    }
     
     // 1. White Balance
-    rgb = rgb * u_whiteBalance;
+    rgb = rgb * a.whiteBalance;
     
     // 2. Exposure
-    float exposureMult = pow(2.0, u_exposure);
+    float exposureMult = pow(2.0, a.exposure);
     rgb = rgb * exposureMult;
     
     // 3. Contrast
-    float contrastFactor = (1.0 + u_contrast) * (1.0 + u_contrast);
+    float contrastFactor = (1.0 + a.contrast) * (1.0 + a.contrast);
     rgb = (rgb - 0.5) * contrastFactor + 0.5;
     
     // --- Advanced Tone Mapping ---
     float luma = getLuminance(rgb);
     
     // Shadows/Highlights
-    float shadowMask = 1.0 - smoothstep(0.0, 0.6, luma);
-    float highlightMask = smoothstep(0.4, 1.0, luma);
+    float shadowMask = 1.0 - smoothstep(0.0, a.shadowRange, luma);
+    float highlightMask = smoothstep(1.0 - a.highlightRange, 1.0, luma);
     
-    if (u_shadows != 0.0) {
-        float shadowLift = pow(2.0, u_shadows) - 1.0;
+    if (a.shadows != 0.0) {
+        float shadowLift = pow(2.0, a.shadows) - 1.0;
         rgb += rgb * shadowLift * shadowMask * 0.5;
     }
     
-    if (u_highlights != 0.0) {
-        float highlightGain = pow(2.0, u_highlights) - 1.0;
+    if (a.highlights != 0.0) {
+        float highlightGain = pow(2.0, a.highlights) - 1.0;
         rgb += rgb * highlightGain * highlightMask * 0.5;
     }
     
+    // Saturation (before levels, on non-negative values)
+    rgb = max(rgb, 0.0);
+    luma = getLuminance(rgb);
+    vec3 grey = vec3(luma);
+    float satMult = 1.0 + a.saturation;
+    if (a.oklab > 0.5) {
+      rgb = oklabSaturate(rgb, satMult);
+    } else {
+      rgb = mix(grey, rgb, satMult);
+    }
+
     // Levels
-    float blackPoint = u_blacks * 0.2;
-    float whitePoint = 1.0 + u_whites * 0.2;
+    float blackPoint = a.blacks * 0.2;
+    float whitePoint = 1.0 + a.whites * 0.2;
     if (whitePoint - blackPoint < 0.001) whitePoint = blackPoint + 0.001;
-    rgb = (rgb - blackPoint) / (whitePoint - blackPoint);
+    rgb = max((rgb - blackPoint) / (whitePoint - blackPoint), 0.0);
 
-    // Saturation
-    luma = getLuminance(rgb);
-    vec3 grey = vec3(luma);
-    float satMult = 1.0 + u_saturation;
-    rgb = mix(grey, rgb, satMult);
+    return rgb;
+  }
+
+  void main() {
+    if (u_rendered > 0.5) {
+      gl_FragColor = vec4(texture2D(u_image, v_texCoord).rgb, 1.0);
+      return;
+    }
+    vec2 uv = v_texCoord;
+    if (u_straighten != 0.0) {
+      vec2 p = (uv - 0.5) * vec2(u_aspect, 1.0);
+      float c = cos(u_straighten);
+      float s = sin(u_straighten);
+      p = vec2(c * p.x + s * p.y, -s * p.x + c * p.y) * u_straightenScale;
+      uv = p / vec2(u_aspect, 1.0) + 0.5;
+    }
+    vec4 color = texture2D(u_image, uv);
+    vec3 rgb = adjust(color.rgb, u_base);
+    for (int i = 0; i < ${MAX_PREVIEW_LAYERS}; i++) {
+      if (i >= u_layerCount) break;
+      rgb = mix(rgb, adjust(rgb, u_layers[i]), u_layerOpacity[i]);
+    }
+
+    // Tone mapping
+    if (u_toneMap > 1.5) {
+      rgb = min((rgb * (2.51 * rgb + 0.03)) / (rgb * (2.43 * rgb + 0.59) + 0.14), 1.0);
+    } else if (u_toneMap > 0.5) {
+      rgb = rgb * (1.0 + rgb / 16.0) / (1.0 + rgb);
+    }
     
     // Gamma
-    float gamma = 1.0 / 2.2;
-    rgb = pow(max(rgb, 0.0), vec3(gamma));
+    rgb = max(rgb, 0.0);
+    if (u_srgbCurve > 0.5) {
+      vec3 toe = rgb * 12.92;
+      vec3 curve = 1.055 * pow(rgb, vec3(1.0 / 2.4)) - 0.055;
+      rgb = mix(curve, toe, step(rgb, vec3(0.0031308)));
+    } else {
+      rgb = pow(rgb, vec3(1.0 / 2.2));
+    }
+
+    // 3D LUT, on the encoded output
+    if (u_hasLut > 0.5) {
+      rgb = clamp(applyLut(rgb), 0.0, 1.0);
+    }
     
     gl_FragColor = vec4(rgb, 1.0);
   }
 `;
 
-// --- Histogram Calculation (CPU JS) ---
-function calculateHistogram(image: ImageResult, params: WebGLParams): HistogramData {
-  const buckets = 256;
-  const hist = {
-    r: new Array(buckets).fill(0),
-    g: new Array(buckets).fill(0),
-    b: new Array(buckets).fill(0),
-    l: new Array(buckets).fill(0),
-  };
+// Mirrors ToneMapping::apply in lib.rs (Reinhard white point 4)
+function toneMapper(mode: WebGLParams['tone_mapping']): (v: number) => number {
+  if (mode === 'aces') {
+    return (v) => Math.min((v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14), 1);
+  }
+  if (mode === 'reinhard') {
+    return (v) => (v * (1 + v / 16)) / (1 + v);
+  }
+  return (v) => v;
+}
 
-  const data = image.data;
-  const step = 20; // 5% sampling
+// Mirrors oklab::adjust in the backend (chroma scale only)
+function oklabSaturate([r, g, b]: number[], mult: number): [number, number, number] {
+  const l = Math.cbrt(0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b);
+  const m = Math.cbrt(0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b);
+  const s = Math.cbrt(0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b);
+  const L = 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s;
+  const A = (1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s) * mult;
+  const B = (0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s) * mult;
+  const l3 = Math.pow(L + 0.3963377774 * A + 0.2158037573 * B, 3);
+  const m3 = Math.pow(L - 0.1055613458 * A - 0.0638541728 * B, 3);
+  const s3 = Math.pow(L - 0.0894841775 * A - 1.2914855480 * B, 3);
+  return [
+    4.0767416621 * l3 - 3.3077115913 * m3 + 0.2309699292 * s3,
+    -1.2684380046 * l3 + 2.6097574011 * m3 - 0.3413193965 * s3,
+    -0.0041960863 * l3 - 0.7034186147 * m3 + 1.7076147010 * s3,
+  ];
+}
 
-  const ratio = (params.temperature - 5500.0) / 5500.0;
-  const wb_r = 1.0 + Math.max(0, ratio);
-  const wb_b = 1.0 - Math.min(0, ratio);
-  const wb_g = 1.0 + params.tint / 100.0;
+// Per-pixel adjustments of one set of params, mirrors adjust() in FS_SOURCE
+function adjuster(params: WebGLParams): (rgb: number[]) => [number, number, number] {
+  const [wb_r, wb_g, wb_b] = whiteBalanceGains(params);
 
   const exposureMult = Math.pow(2.0, params.exposure);
   const contrastFactor = (1.0 + params.contrast) * (1.0 + params.contrast);
   const shadowLift = params.shadows !== 0 ? Math.pow(2.0, params.shadows) - 1.0 : 0;
   const highlightGain = params.highlights !== 0 ? Math.pow(2.0, params.highlights) - 1.0 : 0;
+  const shadowRange = toneRange(params.shadow_range);
+  const highlightRange = toneRange(params.highlight_range);
 
   const blackPoint = params.blacks * 0.2;
   const whitePoint = 1.0 + params.whites * 0.2;
   const range = (whitePoint - blackPoint) < 0.001 ? 0.001 : (whitePoint - blackPoint);
   const satMult = 1.0 + params.saturation;
-  const gammaInv = 1.0 / 2.2;
+  const filmBase = (params.film_base ?? [1, 1, 1]).map((v) => Math.max(v, 1e-6));
 
-  // Data stride is 4 because backend sends RGBA
-  for (let i = 0; i < data.length; i += 4 * step) {
-    let r = data[i];
-    let g = data[i + 1];
-    let b = data[i + 2];
+  return ([r, g, b]) => {
+    if (params.film_negative) {
+      r = Math.max(1 - r / filmBase[0], 0);
+      g = Math.max(1 - g / filmBase[1], 0);
+      b = Math.max(1 - b / filmBase[2], 0);
+    }
 
     // WB
     r *= wb_r; g *= wb_g; b *= wb_b;
@@ -161,11 +530,11 @@ function calculateHistogram(image: ImageResult, params: WebGLParams): HistogramD
     let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
 
     if (shadowLift !== 0 || highlightGain !== 0) {
-      let t_s = luma / 0.6;
+      let t_s = luma / shadowRange;
       t_s = t_s < 0 ? 0 : (t_s > 1 ? 1 : t_s * t_s * (3 - 2 * t_s));
       let shadowMask = 1.0 - t_s;
 
-      let t_h = (luma - 0.4) / 0.6;
+      let t_h = (luma - (1.0 - highlightRange)) / highlightRange;
       let highlightMask = t_h < 0 ? 0 : (t_h > 1 ? 1 : t_h * t_h * (3 - 2 * t_h));
 
       if (shadowLift !== 0) {
@@ -178,21 +547,84 @@ function calculateHistogram(image: ImageResult, params: WebGLParams): HistogramD
       }
     }
 
+    // Saturation (before levels, on non-negative values)
+    r = Math.max(r, 0); g = Math.max(g, 0); b = Math.max(b, 0);
+    if (params.adjustment_space === 'oklab') {
+      [r, g, b] = oklabSaturate([r, g, b], satMult);
+    } else {
+      luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+      r = luma + (r - luma) * satMult;
+      g = luma + (g - luma) * satMult;
+      b = luma + (b - luma) * satMult;
+    }
+
     // Levels
-    r = (r - blackPoint) / range;
-    g = (g - blackPoint) / range;
-    b = (b - blackPoint) / range;
+    r = Math.max((r - blackPoint) / range, 0);
+    g = Math.max((g - blackPoint) / range, 0);
+    b = Math.max((b - blackPoint) / range, 0);
+
+    return [r, g, b];
+  };
+}
+
+// --- Histogram Calculation (CPU JS) ---
+// `rendered` is the backend's display-encoded render, counted as it is
+function calculateHistogram(
+  image: ImageResult,
+  params: WebGLParams,
+  lut: LutData | null,
+  rendered: ImageResult | null,
+): HistogramData {
+  const buckets = 256;
+  const hist = {
+    r: new Array(buckets).fill(0),
+    g: new Array(buckets).fill(0),
+    b: new Array(buckets).fill(0),
+    l: new Array(buckets).fill(0),
+  };
 
-    // Saturation
-    luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
-    r = luma + (r - luma) * satMult;
-    g = luma + (g - luma) * satMult;
-    b = luma + (b - luma) * satMult;
+  const data = (rendered ?? image).data;
+  const step = 20; // 5% sampling
+
+  params = forImage(params, image);
+  const adjust = adjuster(params);
+  const layers = visibleLayers(params)
+    .slice(0, MAX_PREVIEW_LAYERS)
+    .map((layer) => ({ adjust: adjuster(layer.params), opacity: layer.opacity }));
+  const encode = params.transfer_curve === 'gamma22'
+    ? (v: number) => (v > 0 ? Math.pow(v, 1.0 / 2.2) : 0)
+    : (v: number) => (v <= 0.0031308 ? Math.max(v, 0) * 12.92 : 1.055 * Math.pow(v, 1.0 / 2.4) - 0.055);
+  const toneMap = toneMapper(params.tone_mapping);
+  const process = (rgb: number[]): number[] => {
+    let [r, g, b] = adjust(rgb);
+
+    // Layers, mixed in by opacity
+    for (const layer of layers) {
+      const [lr, lg, lb] = layer.adjust([r, g, b]);
+      r += (lr - r) * layer.opacity;
+      g += (lg - g) * layer.opacity;
+      b += (lb - b) * layer.opacity;
+    }
+
+    // Tone mapping
+    r = toneMap(r);
+    g = toneMap(g);
+    b = toneMap(b);
 
     // Gamma
-    r = r > 0 ? Math.pow(r, gammaInv) : 0;
-    g = g > 0 ? Math.pow(g, gammaInv) : 0;
-    b = b > 0 ? Math.pow(b, gammaInv) : 0;
+    r = encode(r);
+    g = encode(g);
+    b = encode(b);
+
+    return lut ? applyLut(lut, [r, g, b]) : [r, g, b];
+  };
+
+  // Data stride is 4 because backend sends RGBA
+  for (let i = 0; i < data.length; i += 4 * step) {
+    let [r, g, b] = [data[i], data[i + 1], data[i + 2]];
+    if (!rendered) {
+      [r, g, b] = process([r, g, b]);
+    }
 
     const ir = Math.min(255, Math.max(0, Math.floor(r * 255)));
     const ig = Math.min(255, Math.max(0, Math.floor(g * 255)));
@@ -283,11 +715,40 @@ function createProgram(gl: WebGLRenderingContext, vs: string, fs: string) {
   return program;
 }
 
-function WebGLViewer({ image, params }: { image: ImageResult | null, params: WebGLParams }) {
+// Fills the Adjust struct uniform `name` of FS_SOURCE from params
+function setAdjustUniforms(gl: WebGLRenderingContext, program: WebGLProgram, name: string, params: WebGLParams) {
+  const loc = (field: string) => gl.getUniformLocation(program, `${name}.${field}`);
+  const [wb_r, wb_g, wb_b] = whiteBalanceGains(params);
+  const [baseR, baseG, baseB] = params.film_base ?? [1, 1, 1];
+
+  gl.uniform1f(loc("filmNegative"), params.film_negative ? 1 : 0);
+  gl.uniform3f(loc("filmBase"), baseR, baseG, baseB);
+  gl.uniform3f(loc("whiteBalance"), wb_r, wb_g, wb_b);
+  gl.uniform1f(loc("exposure"), params.exposure);
+  gl.uniform1f(loc("contrast"), params.contrast);
+  gl.uniform1f(loc("highlights"), params.highlights);
+  gl.uniform1f(loc("shadows"), params.shadows);
+  gl.uniform1f(loc("shadowRange"), toneRange(params.shadow_range));
+  gl.uniform1f(loc("highlightRange"), toneRange(params.highlight_range));
+  gl.uniform1f(loc("saturation"), params.saturation);
+  gl.uniform1f(loc("oklab"), params.adjustment_space === 'oklab' ? 1 : 0);
+  gl.uniform1f(loc("whites"), params.whites);
+  gl.uniform1f(loc("blacks"), params.blacks);
+}
+
+// `rendered`, when set, is shown as it is in place of the shader's processing of `image`
+function WebGLViewer({ image, rendered, params, lut }: {
+  image: ImageResult | null,
+  rendered: ImageResult | null,
+  params: WebGLParams,
+  lut: LutData | null,
+}) {
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const glRef = useRef<WebGLRenderingContext | null>(null);
   const programRef = useRef<WebGLProgram | null>(null);
   const textureRef = useRef<WebGLTexture | null>(null);
+  const lutTextureRef = useRef<WebGLTexture | null>(null);
+  const lutLayoutRef = useRef<{ cols: number; width: number; height: number } | null>(null);
   const frameIdRef = useRef<number>(0);
 
   // Initialize GL
@@ -321,19 +782,42 @@ function WebGLViewer({ image, params }: { image: ImageResult | null, params: Web
       gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MIN_FILTER, gl.NEAREST);
       gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MAG_FILTER, gl.NEAREST);
       textureRef.current = texture;
+
+      const lutTexture = gl.createTexture();
+      gl.bindTexture(gl.TEXTURE_2D, lutTexture);
+      gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_WRAP_S, gl.CLAMP_TO_EDGE);
+      gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_WRAP_T, gl.CLAMP_TO_EDGE);
+      gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MIN_FILTER, gl.LINEAR);
+      gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MAG_FILTER, gl.LINEAR);
+      lutTextureRef.current = lutTexture;
     } catch (e: any) {
       console.error("Setup Error:", e);
     }
   }, []);
 
+  // Upload LUT
+  useEffect(() => {
+    const gl = glRef.current;
+    if (!gl || !lutTextureRef.current) return;
+    if (!lut) {
+      lutLayoutRef.current = null;
+      return;
+    }
+    const { pixels, ...layout } = lutAtlas(lut);
+    gl.bindTexture(gl.TEXTURE_2D, lutTextureRef.current);
+    gl.texImage2D(gl.TEXTURE_2D, 0, gl.RGBA, layout.width, layout.height, 0, gl.RGBA, gl.UNSIGNED_BYTE, pixels);
+    lutLayoutRef.current = layout;
+  }, [lut]);
+
   // Upload Texture
   useEffect(() => {
     const gl = glRef.current;
-    if (!gl || !image || !textureRef.current) return;
+    const source = rendered ?? image;
+    if (!gl || !source || !textureRef.current) return;
 
     try {
       gl.bindTexture(gl.TEXTURE_2D, textureRef.current);
-      const data = new Float32Array(image.data);
+      const data = source.data;
 
       let internalFormat;
       if (gl instanceof WebGL2RenderingContext) {
@@ -342,17 +826,17 @@ function WebGLViewer({ image, params }: { image: ImageResult | null, params: Web
         internalFormat = gl.RGBA;
       }
 
-      gl.texImage2D(gl.TEXTURE_2D, 0, internalFormat, image.width, image.height, 0, gl.RGBA, gl.FLOAT, data);
+      gl.texImage2D(gl.TEXTURE_2D, 0, internalFormat, source.width, source.height, 0, gl.RGBA, gl.FLOAT, data);
 
       if (canvasRef.current) {
-        canvasRef.current.width = image.width;
-        canvasRef.current.height = image.height;
-        gl.viewport(0, 0, image.width, image.height);
+        canvasRef.current.width = source.width;
+        canvasRef.current.height = source.height;
+        gl.viewport(0, 0, source.width, source.height);
       }
     } catch (e: any) {
       console.error("Upload Exception:", e);
     }
-  }, [image]);
+  }, [image, rendered]);
 
   // Render Loop
   useEffect(() => {
@@ -386,25 +870,43 @@ function WebGLViewer({ image, params }: { image: ImageResult | null, params: Web
       gl.enableVertexAttribArray(texCoordLoc);
       gl.vertexAttribPointer(texCoordLoc, 2, gl.FLOAT, false, 0, 0);
 
-      const ratio = (params.temperature - 5500.0) / 5500.0;
-      const wb_r = 1.0 + Math.max(0, ratio);
-      const wb_b = 1.0 - Math.min(0, ratio);
-      const wb_g = 1.0 + params.tint / 100.0;
+      gl.uniform1f(gl.getUniformLocation(program, "u_rendered"), rendered ? 1 : 0);
+
+      const adjusted = forImage(params, image);
+      setAdjustUniforms(gl, program, "u_base", adjusted);
+      const layers = visibleLayers(adjusted).slice(0, MAX_PREVIEW_LAYERS);
+      layers.forEach((layer, i) => {
+        setAdjustUniforms(gl, program, `u_layers[${i}]`, layer.params);
+        gl.uniform1f(gl.getUniformLocation(program, `u_layerOpacity[${i}]`), layer.opacity);
+      });
+      gl.uniform1i(gl.getUniformLocation(program, "u_layerCount"), layers.length);
 
-      gl.uniform1f(gl.getUniformLocation(program, "u_exposure"), params.exposure);
-      gl.uniform1f(gl.getUniformLocation(program, "u_contrast"), params.contrast);
-      gl.uniform3f(gl.getUniformLocation(program, "u_whiteBalance"), wb_r, wb_g, wb_b);
+      gl.uniform1f(gl.getUniformLocation(program, "u_srgbCurve"), params.transfer_curve === 'gamma22' ? 0 : 1);
+      gl.uniform1f(gl.getUniformLocation(program, "u_toneMap"),
+        params.tone_mapping === 'aces' ? 2 : params.tone_mapping === 'reinhard' ? 1 : 0);
 
-      gl.uniform1f(gl.getUniformLocation(program, "u_highlights"), params.highlights);
-      gl.uniform1f(gl.getUniformLocation(program, "u_shadows"), params.shadows);
-      gl.uniform1f(gl.getUniformLocation(program, "u_whites"), params.whites);
-      gl.uniform1f(gl.getUniformLocation(program, "u_blacks"), params.blacks);
-      gl.uniform1f(gl.getUniformLocation(program, "u_saturation"), params.saturation);
+      const straighten = ((params.straighten_angle ?? 0) * Math.PI) / 180;
+      gl.uniform1f(gl.getUniformLocation(program, "u_straighten"), straighten);
+      gl.uniform1f(gl.getUniformLocation(program, "u_straightenScale"), straightenScale(image.width, image.height, straighten));
+      gl.uniform1f(gl.getUniformLocation(program, "u_aspect"), image.width / image.height);
 
       gl.activeTexture(gl.TEXTURE0);
       gl.bindTexture(gl.TEXTURE_2D, textureRef.current);
       gl.uniform1i(gl.getUniformLocation(program, "u_image"), 0);
 
+      const lutLayout = lutLayoutRef.current;
+      gl.uniform1f(gl.getUniformLocation(program, "u_hasLut"), lut && lutLayout ? 1 : 0);
+      if (lut && lutLayout) {
+        gl.activeTexture(gl.TEXTURE1);
+        gl.bindTexture(gl.TEXTURE_2D, lutTextureRef.current);
+        gl.uniform1i(gl.getUniformLocation(program, "u_lut"), 1);
+        gl.uniform1f(gl.getUniformLocation(program, "u_lutSize"), lut.size);
+        gl.uniform1f(gl.getUniformLocation(program, "u_lutCols"), lutLayout.cols);
+        gl.uniform2f(gl.getUniformLocation(program, "u_lutTexSize"), lutLayout.width, lutLayout.height);
+        gl.uniform3f(gl.getUniformLocation(program, "u_lutDomainMin"), ...lut.domain_min);
+        gl.uniform3f(gl.getUniformLocation(program, "u_lutDomainMax"), ...lut.domain_max);
+      }
+
       gl.drawArrays(gl.TRIANGLES, 0, 6);
 
       gl.deleteBuffer(pBuffer);
@@ -416,7 +918,7 @@ function WebGLViewer({ image, params }: { image: ImageResult | null, params: Web
     frameIdRef.current = requestAnimationFrame(render);
     return () => cancelAnimationFrame(frameIdRef.current);
 
-  }, [image, params]);
+  }, [image, rendered, params, lut]);
 
   return (
     <div style={{ position: 'relative', width: '100%', height: '100%', background: '#1a1a1a' }}>
@@ -439,6 +941,10 @@ function App() {
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
   const [histData, setHistData] = useState<HistogramData | null>(null);
+  // Backend render of the preview when the params need it (needsBackendRender)
+  const [rendered, setRendered] = useState<ImageResult | null>(null);
+  const [lut, setLut] = useState<LutData | null>(null);
+  const [exportProgress, setExportProgress] = useState<number | null>(null);
 
   const [params, setParams] = useState<WebGLParams>({
     exposure: 0.0,
@@ -450,16 +956,18 @@ function App() {
     whites: 0.0,
     blacks: 0.0,
     saturation: 0.0,
+    wb_mode: 'as_shot',
   });
 
   const handleOpenFile = async () => {
     try {
+      const extensions = await invoke<string[]>("supported_extensions");
       const file = await open({
         multiple: false,
         directory: false,
         filters: [{
           name: 'RAW Images',
-          extensions: ['arw', 'cr2', 'nef', 'dng', 'raf', 'orf']
+          extensions
         }]
       });
 
@@ -468,34 +976,50 @@ function App() {
         setError(null);
         setImagePath(file as string); // Save path for re-use
 
+        // Show the embedded camera preview while the RAW decodes
+        invoke<ArrayBuffer>("load_thumbnail", { path: file as string })
+          .then((buffer) => setImageResult((prev) => prev ?? decodeImageResponse(buffer)))
+          .catch(() => console.log("No embedded preview"));
+
         try {
-          const data = await invoke<ImageResult>("load_raw", { path: file as string });
-          setImageResult(data);
+          setImageResult(null);
 
-          // Try loading existing params
+          // Try loading existing params first: the WB mode affects decoding
+          let nextParams: WebGLParams;
+          let savedParams = false;
           try {
             const basePath = (file as string).replace(/\.[^/.]+$/, "");
-            const loadedParams = await invoke<WebGLParams>("load_params", { path: `${basePath}.json` });
-            setParams(loadedParams);
+            nextParams = await invoke<WebGLParams>("load_params", { path: `${basePath}.json` });
+            savedParams = true;
             console.log("Loaded existing params");
           } catch (e) {
             console.log("No existing params found, using default");
-            setParams({
-              exposure: 0.0,
-              contrast: 0.0,
-              temperature: 5500.0,
-              tint: 0.0,
-              highlights: 0.0,
-              shadows: 0.0,
-              whites: 0.0,
-              blacks: 0.0,
-              saturation: 0.0,
-            });
+            nextParams = await invoke<WebGLParams>("default_params");
+          }
+          setParams(nextParams);
+
+          const buffer = await invoke<ArrayBuffer>("load_raw", {
+            path: file as string,
+            wbMode: nextParams.wb_mode ?? 'as_shot',
+            autoOrient: nextParams.auto_orient ?? true,
+          });
+          const result = decodeImageResponse(buffer);
+          setImageResult(result);
+          // New edits start the WB sliders at the camera's white balance
+          if (!savedParams && result.as_shot_wb) {
+            const [temperature, tint] = result.as_shot_wb;
+            setParams(prev => ({ ...prev, temperature: Math.round(temperature), tint: Math.round(tint) }));
+          }
+          if (result.color_profile_missing) {
+            setError("No colour profile for this camera: colours may be inaccurate");
+          } else if (result.wb_estimated) {
+            setError("No camera white balance in this file: using daylight");
           }
 
         } catch (e: any) {
+          if (isCancelled(e)) return;
           console.error(e);
-          setError("Failed to load image: " + e);
+          setError("Failed to load image: " + errorMessage(e));
         } finally {
           setLoading(false);
         }
@@ -522,21 +1046,34 @@ function App() {
       const basePath = imagePath.replace(/\.[^/.]+$/, "");
       const savePath = await save({
         defaultPath: `${basePath}.jpg`,
-        filters: [{ name: 'JPEG', extensions: ['jpg'] }]
+        filters: [
+          { name: 'JPEG', extensions: ['jpg'] },
+          { name: 'TIFF (16-bit)', extensions: ['tif', 'tiff'] },
+          { name: 'OpenEXR (linear float)', extensions: ['exr'] },
+          { name: 'WebP', extensions: ['webp'] }
+        ]
       });
 
       if (!savePath) return;
 
       setLoading(true);
-      // Wait for a bit just to show loading state
-      await new Promise(r => setTimeout(r, 100));
+      setExportProgress(0);
+      const unlisten = await listen<number>("export-progress", (event) => {
+        setExportProgress(event.payload);
+      });
 
-      await invoke("export_image", { path: imagePath, params, savePath });
+      try {
+        await invoke("export_image", { path: imagePath, params, savePath });
+      } finally {
+        unlisten();
+      }
       alert("Export Successful!");
     } catch (e) {
-      alert("Export Failed: " + e);
+      if (isCancelled(e)) return;
+      alert("Export Failed: " + errorMessage(e));
     } finally {
       setLoading(false);
+      setExportProgress(null);
     }
   };
 
@@ -544,6 +1081,87 @@ function App() {
     setParams(prev => ({ ...prev, [key]: value }));
   };
 
+  const handleWbPreset = async (name: string) => {
+    if (!name) return;
+    try {
+      const [temperature, tint] = await invoke<[number, number]>("wb_preset", { name });
+      setParams(prev => ({ ...prev, temperature, tint }));
+    } catch (e) {
+      setError(errorMessage(e));
+    }
+  };
+
+  // Switching WB mode changes the decode, so the preview is reloaded
+  const handleWbModeChange = async (mode: 'as_shot' | 'custom') => {
+    setParams(prev => ({ ...prev, wb_mode: mode }));
+    if (!imagePath) return;
+    setLoading(true);
+    try {
+      const buffer = await invoke<ArrayBuffer>("load_raw", {
+        path: imagePath,
+        wbMode: mode,
+        autoOrient: params.auto_orient ?? true,
+      });
+      setImageResult(decodeImageResponse(buffer));
+    } catch (e) {
+      if (isCancelled(e)) return;
+      setError("Failed to reload image: " + errorMessage(e));
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  // The LUT is applied in the preview too, so it's fetched whenever the path changes
+  useEffect(() => {
+    const path = params.apply_lut;
+    if (!path) {
+      setLut(null);
+      return;
+    }
+    let stale = false;
+    invoke<ArrayBuffer>("get_lut", { path })
+      .then((buffer) => {
+        if (!stale) setLut(decodeBinaryResponse<Omit<LutData, 'data'>>(buffer));
+      })
+      .catch((e) => {
+        if (stale) return;
+        setLut(null);
+        setError("Failed to load LUT: " + errorMessage(e));
+      });
+    return () => { stale = true; };
+  }, [params.apply_lut]);
+
+  // A render of the previous image must not show over a new one
+  useEffect(() => setRendered(null), [imageResult]);
+
+  // Backend render for params the shader can't show. Only once load_raw is
+  // done: the embedded thumbnail shown meanwhile isn't the backend's preview.
+  useEffect(() => {
+    if (!imageResult || loading || !needsBackendRender(params)) {
+      setRendered(null);
+      return;
+    }
+    let stale = false;
+    const timer = setTimeout(() => {
+      invoke<ArrayBuffer>("render_preview", { params })
+        .then((buffer) => {
+          if (stale) return;
+          const result = decodeImageResponse(buffer);
+          setRendered(result);
+          if (result.passes_skipped) {
+            setError("Only a crop is loaded: lens, geometry and local corrections aren't previewed");
+          }
+        })
+        .catch((e) => {
+          if (!stale) setError("Failed to render preview: " + errorMessage(e));
+        });
+    }, 50);
+    return () => {
+      stale = true;
+      clearTimeout(timer);
+    };
+  }, [imageResult, params, loading]);
+
   // Histogram Calc
   useEffect(() => {
     if (!imageResult) {
@@ -551,11 +1169,11 @@ function App() {
       return;
     }
     const timer = setTimeout(() => {
-      const h = calculateHistogram(imageResult, params);
+      const h = calculateHistogram(imageResult, params, lut, rendered);
       setHistData(h);
     }, 50);
     return () => clearTimeout(timer);
-  }, [imageResult, params]);
+  }, [imageResult, params, lut, rendered]);
 
   return (
     <div className="app-container">
@@ -568,8 +1186,15 @@ function App() {
           <button onClick={handleExport} disabled={!imagePath} className="secondary">
             Export JPEG
           </button>
+          {exportProgress !== null && (
+            <button onClick={() => invoke("cancel_export")} className="secondary">
+              Cancel Export
+            </button>
+          )}
           <button onClick={handleOpenFile} disabled={loading} className="primary">
-            {loading ? "Processing..." : "Open File"}
+            {exportProgress !== null
+              ? `Exporting ${exportProgress}%`
+              : loading ? "Processing..." : "Open File"}
           </button>
         </div>
       </header>
@@ -577,7 +1202,7 @@ function App() {
       <div className="main-content">
         <div className="image-area">
           {error && <div style={{ color: 'red', position: 'absolute', top: 20 }}>{error}</div>}
-          <WebGLViewer image={imageResult} params={params} />
+          <WebGLViewer image={imageResult} rendered={rendered} params={params} lut={lut} />
         </div>
 
         <aside className="sidebar" style={{ overflowY: 'auto' }}>
@@ -586,13 +1211,38 @@ function App() {
 
           <h3>Basic</h3>
 
+          <div className="control-group">
+            <label className="control-label">White Balance</label>
+            <select
+              value={params.wb_mode ?? 'as_shot'}
+              onChange={(e) => handleWbModeChange(e.target.value as 'as_shot' | 'custom')}
+              disabled={!imageResult || loading || imageResult.monochrome_sensor}
+            >
+              <option value="as_shot">As Shot</option>
+              <option value="custom">Custom</option>
+            </select>
+            <select
+              value=""
+              onChange={(e) => handleWbPreset(e.target.value)}
+              disabled={!imageResult || params.wb_mode !== 'custom'}
+            >
+              <option value="">Preset...</option>
+              <option value="daylight">Daylight</option>
+              <option value="cloudy">Cloudy</option>
+              <option value="shade">Shade</option>
+              <option value="tungsten">Tungsten</option>
+              <option value="fluorescent">Fluorescent</option>
+              <option value="flash">Flash</option>
+            </select>
+          </div>
+
           <div className="control-group">
             <label className="control-label">Temperature ({params.temperature}K)</label>
             <input
               type="range" min="2000" max="10000" step="100"
               value={params.temperature}
               onChange={(e) => handleParamChange('temperature', parseFloat(e.target.value))}
-              disabled={!imageResult}
+              disabled={!imageResult || params.wb_mode !== 'custom'}
             />
           </div>
 
@@ -602,12 +1252,15 @@ function App() {
               type="range" min="-50" max="50" step="1"
               value={params.tint}
               onChange={(e) => handleParamChange('tint', parseFloat(e.target.value))}
-              disabled={!imageResult}
+              disabled={!imageResult || params.wb_mode !== 'custom'}
             />
           </div>
 
           <div className="control-group">
-            <label className="control-label">Exposure ({params.exposure.toFixed(1)})</label>
+            <label className="control-label">
+              Exposure ({params.exposure.toFixed(1)})
+              {imageResult?.baseline_exposure ? ` + ${imageResult.baseline_exposure.toFixed(2)} EV baseline` : ''}
+            </label>
             <input
               type="range" min="-3" max="3" step="0.1"
               value={params.exposure}
@@ -626,6 +1279,18 @@ function App() {
             />
           </div>
 
+          <div className="control-group">
+            <label className="control-label">
+              <input
+                type="checkbox"
+                checked={params.film_negative ?? false}
+                onChange={(e) => setParams(prev => ({ ...prev, film_negative: e.target.checked }))}
+                disabled={!imageResult}
+              />
+              {' '}Film negative
+            </label>
+          </div>
+
           <h3 style={{ marginTop: '20px' }}>Light</h3>
 
           <div className="control-group">
@@ -668,6 +1333,19 @@ function App() {
             />
           </div>
 
+          <div className="control-group">
+            <label className="control-label">Highlight Rolloff</label>
+            <select
+              value={params.tone_mapping ?? 'none'}
+              onChange={(e) => setParams(prev => ({ ...prev, tone_mapping: e.target.value as WebGLParams['tone_mapping'] }))}
+              disabled={!imageResult}
+            >
+              <option value="none">None (clip)</option>
+              <option value="reinhard">Reinhard</option>
+              <option value="aces">Filmic (ACES)</option>
+            </select>
+          </div>
+
           <h3 style={{ marginTop: '20px' }}>Color</h3>
 
           <div className="control-group">
@@ -680,6 +1358,18 @@ function App() {
             />
           </div>
 
+          <div className="control-group">
+            <label className="control-label">Saturation Model</label>
+            <select
+              value={params.adjustment_space ?? 'rgb'}
+              onChange={(e) => setParams(prev => ({ ...prev, adjustment_space: e.target.value as WebGLParams['adjustment_space'] }))}
+              disabled={!imageResult}
+            >
+              <option value="rgb">RGB (legacy)</option>
+              <option value="oklab">OKLab (hue-preserving)</option>
+            </select>
+          </div>
+
 
           <div style={{ marginTop: 'auto', fontSize: '0.8rem', color: '#666', paddingTop: '20px' }}>
             Rust + React + Tauri + WebGL